@@ -0,0 +1,386 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use log::{debug, trace, warn};
+use zip::ZipArchive;
+
+use crate::config::artifacts::ArtifactConfig;
+use crate::download::Download;
+use crate::pattern::ArtifactMatcher;
+use crate::{ArtifactFiles, ErdError, FileData};
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+const XZ_MAGIC: &[u8] = b"\xfd7zXZ\x00";
+/// Tar archives have no magic at the start, instead `ustar` is at this offset of the first header
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    /// A gzip compressed tarball, `.tar.gz`
+    TarGz,
+    /// A zstd compressed tarball, `.tar.zst`
+    TarZst,
+    /// An xz compressed tarball, `.tar.xz`
+    TarXz,
+}
+
+/// Detect the format of an archive from the magic bytes at its start
+pub fn detect_format(data: &[u8]) -> Option<ArchiveFormat> {
+    if data.starts_with(ZIP_MAGIC) {
+        Some(ArchiveFormat::Zip)
+    } else if data.starts_with(GZIP_MAGIC) {
+        Some(ArchiveFormat::TarGz)
+    } else if data.starts_with(ZSTD_MAGIC) {
+        Some(ArchiveFormat::TarZst)
+    } else if data.starts_with(XZ_MAGIC) {
+        Some(ArchiveFormat::TarXz)
+    } else if data.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC) {
+        Some(ArchiveFormat::Tar)
+    } else {
+        None
+    }
+}
+
+/// A file read out of an archive
+struct Entry {
+    data: Download,
+    mode: Option<u32>,
+}
+
+/// A downloaded archive, read from its temporary file
+pub struct Archive {
+    format: ArchiveFormat,
+    file: Download,
+}
+
+impl Archive {
+    pub fn new(file: Download) -> Result<Self, ErdError> {
+        let format = detect_format(&file.header()?).ok_or_else(|| {
+            ErdError::IOError(
+                io::Error::new(io::ErrorKind::InvalidData, "not a zip or tar archive"),
+                "Unrecognised archive format".to_string(),
+            )
+        })?;
+        debug!("Archive format: {:?}", format);
+        if format != ArchiveFormat::TarXz {
+            return Ok(Self { format, file });
+        }
+        // lzma-rs can only decompress into a writer, so decompress the whole tarball up front
+        let reader = &mut BufReader::new(file.open()?);
+        let file = Download::from_writer("Invalid xz archive", |mut writer| {
+            lzma_rs::xz_decompress(reader, &mut writer)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+        })?;
+        Ok(Self {
+            format: ArchiveFormat::Tar,
+            file,
+        })
+    }
+
+    fn open_zip(&self) -> Result<ZipArchive<BufReader<File>>, ErdError> {
+        ZipArchive::new(BufReader::new(self.file.open()?))
+            .map_err(|e| ErdError::IOError(e.into(), "Invalid zip archive".to_string()))
+    }
+
+    /// Open the tarball, decompressing it as it is read if needed
+    fn open_tar(&self) -> Result<tar::Archive<Box<dyn Read>>, ErdError> {
+        let data = BufReader::new(self.file.open()?);
+        let reader: Box<dyn Read> = match self.format {
+            ArchiveFormat::Tar => Box::new(data),
+            ArchiveFormat::TarGz => Box::new(GzDecoder::new(data)),
+            ArchiveFormat::TarZst => Box::new(
+                zstd::Decoder::with_buffer(data)
+                    .map_err(|e| ErdError::IOError(e, "Invalid zstd archive".to_string()))?,
+            ),
+            ArchiveFormat::Zip | ArchiveFormat::TarXz => unreachable!("Not a tarball that is read as a stream"),
+        };
+        Ok(tar::Archive::new(reader))
+    }
+
+    /// List the paths of the files in the archive
+    pub fn file_names(&self) -> Result<Vec<String>, ErdError> {
+        if self.format == ArchiveFormat::Zip {
+            let zip = self.open_zip()?;
+            return Ok((0..zip.len())
+                .filter_map(|i| zip.name_for_index(i))
+                .filter(|f| !f.ends_with('/'))
+                .map(str::to_string)
+                .collect());
+        }
+        let mut names = vec![];
+        let mut tar = self.open_tar()?;
+        for entry in tar.entries().map_err(tar_error)? {
+            let entry = entry.map_err(tar_error)?;
+            if entry.header().entry_type().is_file() {
+                names.push(get_tar_path(&entry)?);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Read the given files out of the archive, in the order they are given
+    fn read_files(&self, names: &[String]) -> Result<Vec<Entry>, ErdError> {
+        if self.format == ArchiveFormat::Zip {
+            let mut zip = self.open_zip()?;
+            return names
+                .iter()
+                .map(|name| {
+                    let file = zip
+                        .by_name(name)
+                        .map_err(|e| ErdError::IOError(e.into(), format!("Failed to find {} in zip", name)))?;
+                    let mode = file.unix_mode();
+                    let data = Download::from_reader(file, &format!("Failed to extract {} from zip", name))?;
+                    Ok(Entry {
+                        data,
+                        mode: mode.map(|m| m & 0o777),
+                    })
+                })
+                .collect();
+        }
+        let mut found: Vec<Option<Entry>> = names.iter().map(|_| None).collect();
+        let mut tar = self.open_tar()?;
+        for entry in tar.entries().map_err(tar_error)? {
+            let entry = entry.map_err(tar_error)?;
+            let path = get_tar_path(&entry)?;
+            // Tarballs can have several entries for the same path, the last of which wins
+            let mut indices = names.iter().enumerate().filter(|(_, n)| **n == path).map(|(i, _)| i);
+            let Some(first) = indices.next() else {
+                continue;
+            };
+            let mode = entry.header().mode().ok().map(|m| m & 0o777);
+            let what = format!("Failed to extract {} from tarball", path);
+            let data = Download::from_reader(entry, &what)?;
+            // The entry can only be read once, so a path asked for again gets a copy
+            for i in indices {
+                let data = Download::from_reader(data.open()?, &what)?;
+                found[i] = Some(Entry { data, mode });
+            }
+            found[first] = Some(Entry { data, mode });
+        }
+        Ok(found
+            .into_iter()
+            .map(|e| e.expect("Files were listed from the same archive"))
+            .collect())
+    }
+}
+
+fn tar_error(error: io::Error) -> ErdError {
+    ErdError::IOError(error, "Invalid tar archive".to_string())
+}
+
+/// Get the path of a tarball entry, without the `./` that some tools add
+fn get_tar_path<R: Read>(entry: &tar::Entry<R>) -> Result<String, ErdError> {
+    let path = entry.path().map_err(tar_error)?;
+    let path = path.to_string_lossy();
+    Ok(path.trim_start_matches("./").to_string())
+}
+
+/// Whether a path from an archive is relative and stays where it is put,
+/// as `..` or an absolute path could escape the download directory
+fn is_safe_path(path: &Path) -> bool {
+    path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Search a downloaded archive (zip or tarball) for the file matching the artifact's pattern,
+/// along with the contents of the artifact's directories.
+/// If the artifact has an inner archive, the search happens within that instead
+pub fn find_artifact_in_archive(
+    file: Download,
+    artifact: &ArtifactConfig,
+) -> Result<Option<ArtifactFiles>, ErdError> {
+    let mut archive = Archive::new(file)?;
+    if let Some(inner_archive) = &artifact.inner_archive {
+        let names = archive.file_names()?;
+        let inner_matcher = ArtifactMatcher::for_pattern(artifact, inner_archive)?;
+        let inner_name = match inner_matcher.select(names, |n| n)? {
+            Some(name) => name,
+            None => {
+                warn!("No archive matching {} was found", inner_archive);
+                return Ok(None);
+            }
+        };
+        debug!("Using inner archive {}", inner_name);
+        let inner = archive
+            .read_files(&[inner_name])?
+            .pop()
+            .expect("One file was requested");
+        archive = Archive::new(inner.data)?;
+    }
+
+    let names = archive.file_names()?;
+    let matcher = ArtifactMatcher::new(artifact)?;
+    let found_jar = matcher.select(names.iter().inspect(|f| trace!("File name: {}", f)), |f| f)?;
+
+    let mut wanted: Vec<(String, PathBuf)> = vec![];
+    if let Some(jar_name) = found_jar {
+        debug!("Found Artifact: {}", jar_name);
+        let path = Path::new(jar_name);
+        let file_name = path.file_name().expect("Could not get filename from path");
+        wanted.push((jar_name.clone(), file_name.into()));
+    }
+    for directory in &artifact.directories {
+        for (name, install_path) in find_directory_files(&names, directory) {
+            // Overlapping directories, or a directory holding the artifact, list some files more than once
+            if !wanted.iter().any(|(n, _)| *n == name) {
+                wanted.push((name, install_path));
+            }
+        }
+    }
+    if wanted.is_empty() {
+        return Ok(None);
+    }
+
+    let (names, install_paths): (Vec<String>, Vec<PathBuf>) = wanted.into_iter().unzip();
+    let files = archive
+        .read_files(&names)?
+        .into_iter()
+        .zip(install_paths)
+        .map(|(entry, file_name)| FileData {
+            file_name,
+            data: entry.data,
+            mode: entry.mode,
+        })
+        .collect();
+    Ok(Some(ArtifactFiles { files }))
+}
+
+/// Find every file under a directory of the archive, along with where to install it.
+/// Paths are kept relative to the directory's parent, so that `build/config` is installed as `config/...`
+fn find_directory_files(names: &[String], directory: &str) -> Vec<(String, PathBuf)> {
+    let directory = Path::new(directory.trim_matches('/'));
+    let parent = directory.parent().unwrap_or(Path::new(""));
+    let mut found = vec![];
+    for name in names {
+        let path = Path::new(name);
+        if !path.starts_with(directory) {
+            continue;
+        }
+        if !is_safe_path(path) {
+            warn!("Skipping unsafe path in archive: {}", name);
+            continue;
+        }
+        let relative = path.strip_prefix(parent).expect("Paths under the directory are under its parent");
+        found.push((name.clone(), relative.to_path_buf()));
+    }
+    if found.is_empty() {
+        warn!("No files were found in directory {:?} of the archive", directory);
+    }
+    found
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    #[test]
+    fn test_find_artifact_with_directories() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        let files: [(&str, &[u8], u32); 5] = [
+            ("build/libs/plugin.jar", b"jar", 0o644),
+            ("build/config/settings.yml", b"settings", 0o644),
+            ("build/config/scripts/start.sh", b"#!/bin/sh", 0o755),
+            ("build/configuration.txt", b"not in the directory", 0o644),
+            ("build/config/../../escape.txt", b"outside", 0o644),
+        ];
+        for (name, data, mode) in files {
+            writer
+                .start_file(name, SimpleFileOptions::default().unix_permissions(mode))
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let buffer = writer.finish().unwrap().into_inner();
+
+        let artifact = ArtifactConfig {
+            id: "plugin".into(),
+            artifact_pattern: "*.jar".into(),
+            // The jar and the scripts are also under other directories, but are only installed once
+            directories: vec!["build/config/".into(), "build/config/scripts".into(), "build/libs".into()],
+            ..Default::default()
+        };
+        let found = find_artifact_in_archive(Download::from_reader(&buffer[..], "").unwrap(), &artifact).unwrap().unwrap();
+        let installed: Vec<(&str, Option<u32>)> = found
+            .files
+            .iter()
+            .map(|f| (f.file_name.to_str().unwrap(), f.mode))
+            .collect();
+        assert_eq!(
+            installed,
+            [
+                ("plugin.jar", Some(0o644)),
+                ("config/settings.yml", Some(0o644)),
+                ("config/scripts/start.sh", Some(0o755)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tarball_with_directories() {
+        let mut tar = tar::Builder::new(vec![]);
+        for (name, data) in [("dist/plugin.jar", &b"jar"[..]), ("dist/config.yml", b"config")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, name, data).unwrap();
+        }
+        let tarball = tar.into_inner().unwrap();
+
+        // The jar is also in the directory, but only installed once
+        let artifact = ArtifactConfig {
+            id: "plugin".into(),
+            artifact_pattern: "*.jar".into(),
+            directories: vec!["dist".into()],
+            ..Default::default()
+        };
+        let found = find_artifact_in_archive(Download::from_reader(&tarball[..], "").unwrap(), &artifact).unwrap().unwrap();
+        let installed: Vec<(&str, Vec<u8>)> = found
+            .files
+            .iter()
+            .map(|f| (f.file_name.to_str().unwrap(), f.data.to_vec()))
+            .collect();
+        assert_eq!(installed, [("plugin.jar", b"jar".to_vec()), ("dist/config.yml", b"config".to_vec())]);
+
+        let archive = Archive::new(Download::from_reader(&tarball[..], "").unwrap()).unwrap();
+        let entries = archive.read_files(&["dist/plugin.jar".into(), "dist/plugin.jar".into()]).unwrap();
+        let data: Vec<Vec<u8>> = entries.iter().map(|e| e.data.to_vec()).collect();
+        assert_eq!(data, [b"jar", b"jar"]);
+    }
+
+    #[test]
+    fn test_tarball_nested_in_zip() {
+        let mut tar = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+        for (name, data) in [("./dist/plugin.jar", &b"jar in tarball"[..]), ("./dist/README.md", b"readme")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o640);
+            tar.append_data(&mut header, name, data).unwrap();
+        }
+        let tarball = tar.into_inner().unwrap().finish().unwrap();
+        assert_eq!(detect_format(&tarball), Some(ArchiveFormat::TarGz));
+
+        let zip = crate::sources::test_server::zip_of(&[("build/dist.tar.gz", &tarball)]);
+        let artifact = ArtifactConfig {
+            id: "plugin".into(),
+            artifact_pattern: "*.jar".into(),
+            inner_archive: Some("*.tar.gz".into()),
+            ..Default::default()
+        };
+        let mut found = find_artifact_in_archive(Download::from_reader(&zip[..], "").unwrap(), &artifact).unwrap().unwrap();
+        let file_data = found.files.remove(0);
+        assert_eq!(file_data.file_name.to_str(), Some("plugin.jar"));
+        assert_eq!(file_data.data.to_vec(), b"jar in tarball");
+        assert_eq!(file_data.mode, Some(0o640));
+    }
+}
@@ -0,0 +1,360 @@
+//! Builds that have been fetched before are kept in `.erd/cache`, so that fetching them again
+//! (e.g. rolling back to an earlier build) does not download them from the source.
+//! Setting `ERD_CACHE_DIR` moves the cache elsewhere, so that several directories installing
+//! the same artifacts can share one.
+//! Files are stored once under their SHA-256, however many builds they are part of,
+//! and each build records which files it is made of.
+//! The latest build is only cached if its source can say which build that is,
+//! see [`ArtifactSource::latest_build_id`](crate::sources::ArtifactSource::latest_build_id).
+
+use std::collections::HashSet;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+
+use crate::config::artifacts::ArtifactConfig;
+use crate::download::Download;
+use crate::sources::to_hex;
+use crate::{config, ArtifactFiles, ErdError, FileData};
+
+/// The environment variable that overrides where the cache is
+const CACHE_DIR_VAR: &str = "ERD_CACHE_DIR";
+
+/// A build of an artifact that is in the cache
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedBuild {
+    /// The URL of the source, as source ids are only unique within a directory's config
+    pub source: String,
+    /// The id of the artifact that the build was cached for
+    pub artifact: String,
+    pub project_id: String,
+    pub build_id: String,
+    pub files: Vec<CachedFile>,
+    /// When the build was last stored or fetched from the cache, in seconds since the unix epoch
+    pub last_used: u64,
+}
+
+impl CachedBuild {
+    /// The total size of the build's files
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|f| f.size).sum()
+    }
+}
+
+/// A single file of a [`CachedBuild`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedFile {
+    pub file_name: PathBuf,
+    pub sha256: String,
+    pub size: u64,
+    pub mode: Option<u32>,
+}
+
+/// What [`Cache::prune`] removed
+pub struct Pruned {
+    pub builds: usize,
+    pub bytes: u64,
+}
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    /// Open the cache in the directory given by `ERD_CACHE_DIR`,
+    /// or else in erd's local directory, if there is one
+    pub fn open() -> Option<Self> {
+        if let Some(dir) = env::var_os(CACHE_DIR_VAR).filter(|d| !d.is_empty()) {
+            return Some(Self::new_in(dir.into()));
+        }
+        let local_dir = config::get_local_dir();
+        local_dir.is_dir().then(|| Self::new_in(local_dir.join("cache")))
+    }
+
+    fn new_in(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn builds_dir(&self) -> PathBuf {
+        self.dir.join("builds")
+    }
+
+    fn objects_dir(&self) -> PathBuf {
+        self.dir.join("objects")
+    }
+
+    fn object_path(&self, sha256: &str) -> PathBuf {
+        self.objects_dir().join(sha256)
+    }
+
+    /// Where the given build is recorded. The artifact's whole configuration is part of the key,
+    /// so that changing its patterns does not give back the files that the old ones picked.
+    /// Its id is not, so that directories naming the same artifact differently share builds
+    fn build_path(&self, source: &str, artifact: &ArtifactConfig, build_id: &str) -> PathBuf {
+        let artifact = ArtifactConfig {
+            id: String::new(),
+            ..artifact.clone()
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(source);
+        hasher.update([0]);
+        hasher.update(serde_json::to_vec(&artifact).expect("Artifact configs should always serialize"));
+        hasher.update([0]);
+        hasher.update(build_id);
+        self.builds_dir().join(format!("{:x}.json", hasher.finalize()))
+    }
+
+    /// Get the files of a build that was cached, or None if it wasn't or its files have gone
+    pub fn get(
+        &self,
+        source: &str,
+        artifact: &ArtifactConfig,
+        build_id: &str,
+    ) -> Result<Option<ArtifactFiles>, ErdError> {
+        let path = self.build_path(source, artifact, build_id);
+        let Some(mut build) = read_build(&path) else {
+            return Ok(None);
+        };
+        let mut files = vec![];
+        for file in &build.files {
+            let data = match File::open(self.object_path(&file.sha256)) {
+                Ok(f) => Some(Download::from_reader(f, "Failed to read cached file")?),
+                Err(_) => None,
+            };
+            match data {
+                Some(data) if to_hex(data.sha256()) == file.sha256 => files.push(FileData {
+                    file_name: file.file_name.clone(),
+                    data,
+                    mode: file.mode,
+                }),
+                _ => {
+                    warn!("Cached file {:?} is missing or corrupt, fetching it again", file.file_name);
+                    let _ = fs::remove_file(&path);
+                    return Ok(None);
+                }
+            }
+        }
+        build.last_used = now();
+        write_build(&path, &build)?;
+        debug!("Using cached build {} of {}", build_id, artifact.id);
+        Ok(Some(ArtifactFiles { files }))
+    }
+
+    /// Keep the files of a build, so that it can be fetched again without the source
+    pub fn store(
+        &self,
+        source: &str,
+        artifact: &ArtifactConfig,
+        build_id: &str,
+        artifact_files: &ArtifactFiles,
+    ) -> Result<(), ErdError> {
+        let objects_dir = self.objects_dir();
+        fs::create_dir_all(&objects_dir)
+            .and_then(|_| fs::create_dir_all(self.builds_dir()))
+            .map_err(|e| ErdError::IOError(e, "Failed to create cache directory".to_string()))?;
+        let mut files = vec![];
+        for file_data in &artifact_files.files {
+            let sha256 = to_hex(file_data.data.sha256());
+            let object = self.object_path(&sha256);
+            if !object.exists() {
+                // Written under a temporary name first, so that an interrupted copy is never mistaken for the file
+                let mut temp = NamedTempFile::new_in(&objects_dir)
+                    .map_err(|e| ErdError::IOError(e, "Failed to create cached file".to_string()))?;
+                io::copy(&mut file_data.data.open()?, &mut temp)
+                    .map_err(|e| ErdError::IOError(e, "Failed to write cached file".to_string()))?;
+                temp.persist(&object)
+                    .map_err(|e| ErdError::IOError(e.error, "Failed to write cached file".to_string()))?;
+            }
+            files.push(CachedFile {
+                file_name: file_data.file_name.clone(),
+                sha256,
+                size: file_data.data.len(),
+                mode: file_data.mode,
+            });
+        }
+        let build = CachedBuild {
+            source: source.to_string(),
+            artifact: artifact.id.clone(),
+            project_id: artifact.project_id.clone(),
+            build_id: build_id.to_string(),
+            files,
+            last_used: now(),
+        };
+        write_build(&self.build_path(source, artifact, build_id), &build)
+    }
+
+    /// List the cached builds, most recently used first
+    pub fn list(&self) -> Result<Vec<CachedBuild>, ErdError> {
+        let mut builds: Vec<CachedBuild> = self.read_builds()?.into_iter().map(|(_, b)| b).collect();
+        builds.sort_by_key(|b| std::cmp::Reverse(b.last_used));
+        Ok(builds)
+    }
+
+    /// Remove builds that have not been used for longer than `max_age`, then the least recently used
+    /// until the cache is no bigger than `max_size`. With neither, the whole cache is emptied
+    pub fn prune(&self, max_age: Option<Duration>, max_size: Option<u64>) -> Result<Pruned, ErdError> {
+        let mut builds = self.read_builds()?;
+        builds.sort_by_key(|(_, b)| std::cmp::Reverse(b.last_used));
+        let clear = max_age.is_none() && max_size.is_none();
+        let oldest = max_age.map(|age| now().saturating_sub(age.as_secs()));
+        let mut kept: HashSet<String> = HashSet::new();
+        let mut size = 0;
+        let mut pruned = Pruned { builds: 0, bytes: 0 };
+        for (path, build) in builds {
+            let too_old = oldest.is_some_and(|oldest| build.last_used < oldest);
+            // Files shared with a more recently used build are already counted
+            let added: u64 = build
+                .files
+                .iter()
+                .filter(|f| !kept.contains(&f.sha256))
+                .map(|f| f.size)
+                .sum();
+            let too_big = max_size.is_some_and(|max| size + added > max);
+            if clear || too_old || too_big {
+                fs::remove_file(&path)
+                    .map_err(|e| ErdError::IOError(e, format!("Failed to remove cached build {:?}", path)))?;
+                pruned.builds += 1;
+                continue;
+            }
+            size += added;
+            kept.extend(build.files.into_iter().map(|f| f.sha256));
+        }
+        // Remove the files that no remaining build is made of, including any left over from interrupted copies
+        let objects = match fs::read_dir(self.objects_dir()) {
+            Ok(objects) => objects,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(pruned),
+            Err(e) => return Err(ErdError::IOError(e, "Failed to read cache".to_string())),
+        };
+        for object in objects.flatten() {
+            if kept.contains(&*object.file_name().to_string_lossy()) {
+                continue;
+            }
+            let len = object.metadata().map_or(0, |m| m.len());
+            fs::remove_file(object.path())
+                .map_err(|e| ErdError::IOError(e, format!("Failed to remove cached file {:?}", object.path())))?;
+            pruned.bytes += len;
+        }
+        Ok(pruned)
+    }
+
+    fn read_builds(&self) -> Result<Vec<(PathBuf, CachedBuild)>, ErdError> {
+        let entries = match fs::read_dir(self.builds_dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(ErdError::IOError(e, "Failed to read cache".to_string())),
+        };
+        Ok(entries
+            .flatten()
+            .filter_map(|entry| Some((entry.path(), read_build(&entry.path())?)))
+            .collect())
+    }
+}
+
+fn read_build(path: &Path) -> Option<CachedBuild> {
+    serde_json::from_slice(&fs::read(path).ok()?).ok()
+}
+
+fn write_build(path: &Path, build: &CachedBuild) -> Result<(), ErdError> {
+    fs::write(path, serde_json::to_vec(build).expect("Cached builds should always serialize"))
+        .map_err(|e| ErdError::IOError(e, "Failed to write cached build".to_string()))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Parse a size such as `500M` or `2G`, in bytes. Units are powers of 1024
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (number, unit) = size.split_at(size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len()));
+    let shift = match unit.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("Unknown unit '{}', expected K, M, G or T", unit)),
+    };
+    let number: u64 = number.parse().map_err(|_| format!("Invalid size '{}'", size))?;
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("Size '{}' is too big", size))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn artifact_files(contents: &[u8]) -> ArtifactFiles {
+        FileData {
+            file_name: "plugin.jar".into(),
+            data: Download::from_reader(contents, "Failed to read").unwrap(),
+            mode: None,
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new_in(dir.path().into());
+        let artifact = ArtifactConfig {
+            id: "plugin".into(),
+            project_id: "42".into(),
+            artifact_pattern: ".jar".into(),
+            ..Default::default()
+        };
+        assert!(cache.get("https://gitlab.com", &artifact, "7").unwrap().is_none());
+
+        cache.store("https://gitlab.com", &artifact, "7", &artifact_files(b"build 7")).unwrap();
+        cache.store("https://gitlab.com", &artifact, "8", &artifact_files(b"build 8")).unwrap();
+        // The same file as build 8, so it is only stored once
+        cache.store("https://gitlab.com", &artifact, "9", &artifact_files(b"build 8")).unwrap();
+        let cached = cache.get("https://gitlab.com", &artifact, "7").unwrap().expect("Build 7 was not cached");
+        assert_eq!(cached.files[0].file_name.to_str(), Some("plugin.jar"));
+        assert_eq!(cached.files[0].data.to_vec(), b"build 7");
+        assert_eq!(cache.list().unwrap().len(), 3);
+        assert_eq!(fs::read_dir(dir.path().join("objects")).unwrap().count(), 2);
+
+        let changed = ArtifactConfig {
+            artifact_pattern: "*-all.jar".into(),
+            ..artifact.clone()
+        };
+        assert!(cache.get("https://gitlab.com", &changed, "7").unwrap().is_none());
+        // Another directory may name the artifact differently
+        let renamed = ArtifactConfig {
+            id: "gitlab-plugin".into(),
+            ..artifact.clone()
+        };
+        assert!(cache.get("https://gitlab.com", &renamed, "7").unwrap().is_some());
+
+        // Builds 8 and 9 were last used a while ago
+        for (path, mut build) in cache.read_builds().unwrap() {
+            if build.build_id != "7" {
+                build.last_used -= 100;
+                write_build(&path, &build).unwrap();
+            }
+        }
+        // Each build is 7 bytes, and builds 8 and 9 share theirs
+        let pruned = cache.prune(None, Some(10)).unwrap();
+        assert_eq!((pruned.builds, pruned.bytes), (2, 7));
+        assert_eq!(cache.list().unwrap()[0].build_id, "7");
+
+        let pruned = cache.prune(None, None).unwrap();
+        assert_eq!((pruned.builds, pruned.bytes), (1, 7));
+        assert!(cache.list().unwrap().is_empty());
+
+        assert_eq!(parse_size("500M"), Ok(500 << 20));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert!(parse_size("2 parsecs").is_err());
+    }
+}
@@ -0,0 +1,39 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::info;
+
+use crate::cache::Cache;
+use crate::output::{format_size, CacheListOutput, OutputOptions};
+use crate::ErdError;
+
+pub fn list_cache(options: &OutputOptions) -> Result<(), ErdError> {
+    let builds = match Cache::open() {
+        Some(cache) => cache.list()?,
+        None => vec![],
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let output = CacheListOutput {
+        builds,
+        now,
+        options: options.clone(),
+    };
+    info!("{}", output);
+    Ok(())
+}
+
+pub fn prune_cache(max_age_days: Option<u64>, max_size: Option<u64>) -> Result<(), ErdError> {
+    let Some(cache) = Cache::open() else {
+        info!("Nothing is cached");
+        return Ok(());
+    };
+    let max_age = max_age_days.map(|days| Duration::from_secs(days * 24 * 60 * 60));
+    let pruned = cache.prune(max_age, max_size)?;
+    info!(
+        "Removed {} cached builds, freeing {}",
+        pruned.builds,
+        format_size(pruned.bytes)
+    );
+    Ok(())
+}
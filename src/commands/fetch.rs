@@ -1,14 +1,17 @@
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 use log::{debug, info, warn, error};
 
+use crate::cache::Cache;
 use crate::logins::Logins;
-use crate::gitlab::get_artifact_gitlab;
-use crate::output::{self, FormatOutput, OutputOptions};
-use crate::{config, sha256sum_file, sha256sum_mem, ErdError, FileData};
-use crate::config::artifacts::{ArtifactConfig, Config, SourceType};
+use crate::sources::{self, ArtifactSource};
+use crate::output::{FormatOutput, OutputOptions};
+use crate::{config, sha256sum_file, ErdError, FileData};
+use crate::config::artifacts::{ArtifactConfig, Config, SourceConfig};
 
 pub enum GetArtifactAnswer {
     /// Failed to find an artifact file within the output of a job
@@ -17,80 +20,189 @@ pub enum GetArtifactAnswer {
     NewArtifact(String),
     /// Found an artifact, but it was identical to the existing artifact
     UpToDate(String),
+    /// Failed to fetch the artifact, when fetching several at once.
+    /// Shared, as a source that can't be created fails all of its artifacts
+    Failed(Arc<ErdError>),
 }
 
-pub fn fetch(config: &Config, logins: &Logins, artifact_id: Option<String>, build_id: Option<String>, options: &OutputOptions) -> Result<(), ErdError> {
+/// Where fetched artifacts are installed, and the cache that they are fetched through
+pub struct Destination {
+    output_dir: PathBuf,
+    cache: Option<Cache>,
+}
+
+impl Destination {
+    /// The downloads directory of erd's local directory, fetching through the cache
+    pub fn local() -> Self {
+        Self {
+            output_dir: config::get_local_dir().join("downloads"),
+            cache: Cache::open(),
+        }
+    }
+}
+
+/// A source shared by the threads fetching its artifacts, or why it couldn't be created
+type SharedSource = Result<Box<dyn ArtifactSource>, Arc<ErdError>>;
+
+pub fn fetch(
+    config: &Config,
+    logins: &Logins,
+    artifact_id: Option<String>,
+    build_id: Option<String>,
+    jobs: usize,
+    destination: &Destination,
+    options: &OutputOptions,
+) -> Result<(), ErdError> {
     match artifact_id {
         Some(art_id) => {
-            let answer = fetch_single(config, logins, &art_id, build_id)?;
-            print_fetch_answer(answer, &art_id, 0, &options);
+            let answer = fetch_single(config, logins, &art_id, build_id, destination)?;
+            print_fetch_answer(answer, &art_id, 0, options);
         }
         None => {
-            let answers = fetch_all(config, logins)?;
+            let answers = fetch_all(config, logins, jobs, destination);
             let longest_id = answers.iter()
                 .map(|(id, _answer)| id.len())
                 .max();
+            let total = answers.len();
+            let failed = answers
+                .iter()
+                .filter(|(_id, answer)| matches!(answer, GetArtifactAnswer::NotFound | GetArtifactAnswer::Failed(_)))
+                .count();
             match longest_id {
                 Some(padding) => {
                     for (id, answer) in answers {
-                        print_fetch_answer(answer, &id, padding, &options);
+                        print_fetch_answer(answer, &id, padding, options);
                     }
                 }
                 None => {
                     warn!("No artifacts found!")
                 }
             }
-            
+            if failed > 0 {
+                return Err(ErdError::FetchFailed { failed, total });
+            }
         }
     }
     Ok(())
 }
 
-pub fn fetch_single(config: &Config, logins: &Logins, art_id: &str, build_id: Option<String>)  -> Result<GetArtifactAnswer, ErdError> {
+pub fn fetch_single(
+    config: &Config,
+    logins: &Logins,
+    art_id: &str,
+    build_id: Option<String>,
+    destination: &Destination,
+) -> Result<GetArtifactAnswer, ErdError> {
     // Fetch specific artifact
     let (source, artifact) = config
         .sources
         .iter()
         .find_map(|s| s.artifacts.iter().find(|a| a.id == art_id).map(|a| (s, a)))
         .ok_or(ErdError::NoSuchArtifact(art_id.to_owned()))?;
-    let login = logins.find_login(&source.url).ok_or_else(|| 
-        ErdError::NoLogin { source_url: source.url.clone() }
-    )?;
-    let answer = get_artifact(artifact, &source.kind, &login.password, build_id)?;
+    let artifact_source = sources::create_source(source, logins.find_login(&source.url))?;
+    let answer = get_artifact(&*artifact_source, &source.url, artifact, build_id, destination)?;
     return Ok(answer);
 }
 
-pub fn fetch_all(config: &Config, logins: &Logins) -> Result<Vec<(String, GetArtifactAnswer)>, ErdError> {
-    // Fetch all artifacts
-    let mut answers = vec![];
-    for source in &config.sources {
-        for art in &source.artifacts {
-            debug!("Retrieving {} from {}", art.id, source.id);
-            let login = logins.find_login(&source.url).ok_or_else(||
-                ErdError::NoLogin { source_url: source.url.clone() }
-            )?;
-
-            let answer = get_artifact(art, &source.kind, &login.password, None)?;
-            answers.push((art.id.clone(), answer));
+/// Fetch every artifact, up to `jobs` at a time. A failure to fetch one artifact
+/// does not stop the others, instead it is given as that artifact's answer
+pub fn fetch_all(
+    config: &Config,
+    logins: &Logins,
+    jobs: usize,
+    destination: &Destination,
+) -> Vec<(String, GetArtifactAnswer)> {
+    // Each source (and its HTTP client) is created once and shared by its artifacts
+    let sources: Vec<SharedSource> = config
+        .sources
+        .iter()
+        .map(|s| sources::create_source(s, logins.find_login(&s.url)).map_err(Arc::new))
+        .collect();
+    let artifacts: Vec<(&SourceConfig, &SharedSource, &ArtifactConfig)> = config
+        .sources
+        .iter()
+        .zip(&sources)
+        .flat_map(|(s, source)| s.artifacts.iter().map(move |a| (s, source, a)))
+        .collect();
+    let next = AtomicUsize::new(0);
+    let answers: Mutex<Vec<Option<GetArtifactAnswer>>> = Mutex::new(artifacts.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, artifacts.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some((source, artifact_source, art)) = artifacts.get(i) else {
+                    break;
+                };
+                debug!("Retrieving {} from {}", art.id, source.id);
+                let answer = match artifact_source {
+                    Ok(artifact_source) => get_artifact(&**artifact_source, &source.url, art, None, destination).map_err(Arc::new),
+                    Err(e) => Err(e.clone()),
+                }
+                .unwrap_or_else(GetArtifactAnswer::Failed);
+                answers.lock().expect("Answers lock was poisoned")[i] = Some(answer);
+            });
         }
-    }
-    return Ok(answers);
+    });
+    let answers = answers.into_inner().expect("Answers lock was poisoned");
+    artifacts
+        .into_iter()
+        .zip(answers)
+        .map(|((_, _, art), answer)| (art.id.clone(), answer.expect("Every artifact is fetched")))
+        .collect()
 }
 
 fn get_artifact(
+    source: &dyn ArtifactSource,
+    source_url: &str,
     artifact: &ArtifactConfig,
-    kind: &SourceType,
-    token: &str,
     build_id: Option<String>,
+    destination: &Destination,
 ) -> Result<GetArtifactAnswer, ErdError> {
-    let mut output_dir = config::get_local_dir();
-    output_dir.push("downloads");
-
-    std::fs::create_dir_all(&output_dir)
+    let output_dir = &destination.output_dir;
+    std::fs::create_dir_all(output_dir)
         .map_err(|e| ErdError::IOError(e, "Failed to create output dir".to_string()))?;
 
-    let file_data = match kind {
-        SourceType::Gitlab => get_artifact_gitlab(artifact, token, build_id)?,
+    // A build that was fetched before needs nothing more from the source,
+    // so find out which build the latest one is if the source can say
+    let cache = &destination.cache;
+    let build_id = match (cache, build_id) {
+        (Some(_), None) => source.latest_build_id(artifact)?,
+        (_, b_id) => b_id,
+    };
+    let cached = match (cache, build_id.as_deref()) {
+        (Some(cache), Some(b_id)) => cache.get(source_url, artifact, b_id)?,
+        _ => None,
+    };
+
+    // Skip the download entirely if the source can tell us the file is unchanged
+    let remote_hash = match cached {
+        Some(_) => None,
+        None => source.remote_hash(artifact, build_id.as_deref())?,
+    };
+    if let Some(remote) = remote_hash {
+        let output_file = output_dir.join(&remote.file_name);
+        if output_file.exists() {
+            let existing_hash = sha256sum_file(&output_file)
+                .map_err(|e| ErdError::IOError(e, "Failed to read existing file".into()))?;
+            if existing_hash == remote.sha256 {
+                debug!("{:?} matches the published hash", remote.file_name);
+                return Ok(GetArtifactAnswer::UpToDate(remote.file_name.to_string_lossy().to_string()));
+            }
+        }
+    }
+
+    let artifact_files = match cached {
+        Some(files) => Some(files),
+        None => {
+            let artifact_files = source.download(artifact, build_id.clone())?;
+            if let (Some(cache), Some(b_id), Some(files)) = (cache, &build_id, &artifact_files) {
+                // The build was still fetched, so failing to cache it is not worth failing over
+                if let Err(e) = cache.store(source_url, artifact, b_id, files) {
+                    warn!("Failed to cache build {} of {}: {}", b_id, artifact.id, e);
+                }
+            }
+            artifact_files
+        }
     };
 
     fn is_new(output_file: &Path, file_data: &FileData) -> Result<bool, ErdError> {
@@ -100,44 +212,130 @@ fn get_artifact(
         debug!("{:?} already exists, checking if same", file_data.file_name);
         let existing_hash = sha256sum_file(output_file)
             .map_err(|e| ErdError::IOError(e, "Failed to read existing file".into()))?;
-        let new_hash = sha256sum_mem(file_data)
-            .map_err(|e| ErdError::IOError(e, "Failed to calculate new hash".into()))?;
-        Ok(existing_hash != new_hash)
+        Ok(existing_hash != file_data.data.sha256())
     }
 
-    Ok(match file_data {
+    Ok(match artifact_files {
         Some(art) => {
-            let filename_string = art.file_name.to_string_lossy().to_string();
-
-            let output_file = output_dir.join(&art.file_name);
-
-            if !is_new(&output_file, &art)? {
-                return Ok(GetArtifactAnswer::UpToDate(filename_string));
+            let description = art.describe();
+            let mut changed: Vec<(PathBuf, FileData)> = vec![];
+            for file_data in art.files {
+                let output_file = get_output_path(output_dir, &file_data.file_name)?;
+                if is_new(&output_file, &file_data)? {
+                    changed.push((output_file, file_data));
+                }
+            }
+            // The artifact is only up to date if every one of its files is
+            if changed.is_empty() {
+                return Ok(GetArtifactAnswer::UpToDate(description));
             }
 
-            let mut jar_file = File::create(output_file)
-                .map_err(|e| ErdError::IOError(e, "Failed to create Artifact file".to_string()))?;
-            jar_file
-                .write_all(&art.data)
-                .map_err(|e| ErdError::IOError(e, "Failed to write Artifact".into()))?;
+            for (output_file, file_data) in changed {
+                write_file(&output_file, file_data)?;
+            }
 
-            GetArtifactAnswer::NewArtifact(filename_string)
+            GetArtifactAnswer::NewArtifact(description)
         }
         None => GetArtifactAnswer::NotFound,
     })
 }
 
+/// Get where to install a file, refusing any path that would escape the output directory
+fn get_output_path(output_dir: &Path, file_name: &Path) -> Result<PathBuf, ErdError> {
+    let is_safe = file_name.components().all(|c| matches!(c, Component::Normal(_)));
+    if !is_safe || file_name.as_os_str().is_empty() {
+        return Err(ErdError::IOError(
+            io::Error::new(io::ErrorKind::InvalidData, format!("{:?} is outside of the output directory", file_name)),
+            "Refusing to write artifact".to_string(),
+        ));
+    }
+    Ok(output_dir.join(file_name))
+}
+
+fn write_file(output_file: &Path, file_data: FileData) -> Result<(), ErdError> {
+    if let Some(parent) = output_file.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| ErdError::IOError(e, format!("Failed to create {:?}", parent)))?;
+    }
+    file_data.data.persist(output_file)?;
+    #[cfg(unix)]
+    if let Some(mode) = file_data.mode {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(output_file, std::fs::Permissions::from_mode(mode))
+            .map_err(|e| ErdError::IOError(e, format!("Failed to set permissions of {:?}", output_file)))?;
+    }
+    Ok(())
+}
+
 fn print_fetch_answer(
     answer: GetArtifactAnswer,
     artifact_id: &str,
     padding: usize,
     options: &OutputOptions,
 ) {
-    let error = matches!(&answer, GetArtifactAnswer::NotFound);
+    let error = matches!(&answer, GetArtifactAnswer::NotFound | GetArtifactAnswer::Failed(_));
     let answer_output = answer.format_output(options);
     if error {
         error!("{:padding$} {}", artifact_id, answer_output);
     } else {
         info!("{:padding$} {}", artifact_id, answer_output);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::config::artifacts::SourceType;
+
+    #[test]
+    fn test_fetch_all() {
+        let root = tempfile::tempdir().unwrap();
+        for project in ["first", "third"] {
+            let build_dir = root.path().join(project).join("main").join("1");
+            fs::create_dir_all(&build_dir).unwrap();
+            fs::write(build_dir.join(format!("{}.jar", project)), project).unwrap();
+        }
+        let artifact = |project: &str| ArtifactConfig {
+            id: project.into(),
+            project_id: project.into(),
+            branch: "main".into(),
+            artifact_pattern: ".jar".into(),
+            ..Default::default()
+        };
+        let config = Config {
+            sources: vec![SourceConfig {
+                id: "local".into(),
+                kind: SourceType::Local,
+                url: root.path().to_string_lossy().to_string(),
+                region: None,
+                command: None,
+                retries: None,
+                http: None,
+                // The second project doesn't exist
+                artifacts: vec![artifact("first"), artifact("second"), artifact("third")],
+            }],
+        };
+        let output_dir = tempfile::tempdir().unwrap();
+        let destination = Destination {
+            output_dir: output_dir.path().into(),
+            cache: None,
+        };
+
+        let answers = fetch_all(&config, &Logins::default(), 2, &destination);
+        let ids: Vec<&str> = answers.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, ["first", "second", "third"]);
+        assert!(matches!(answers[0].1, GetArtifactAnswer::NewArtifact(_)));
+        assert!(matches!(answers[1].1, GetArtifactAnswer::Failed(_)));
+        assert!(matches!(answers[2].1, GetArtifactAnswer::NewArtifact(_)));
+        assert_eq!(fs::read(output_dir.path().join("third.jar")).unwrap(), b"third");
+
+        // Any failure makes erd exit with an error
+        let options = OutputOptions { color: false, short: false };
+        match fetch(&config, &Logins::default(), None, None, 2, &destination, &options) {
+            Err(ErdError::FetchFailed { failed, total }) => assert_eq!((failed, total), (1, 3)),
+            _ => panic!("Expected fetching to fail"),
+        }
+    }
+}
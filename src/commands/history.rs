@@ -1,15 +1,32 @@
-use crate::{logins::Login, gitlab::get_history_gitlab, ErdError};
+use log::info;
 
-use crate::config::artifacts::{ArtifactConfig, SourceType};
+use crate::config::artifacts::ArtifactConfig;
+use crate::output::{FormatOutput, OutputOptions};
+use crate::sources::{ArtifactSource, Operation};
+use crate::ErdError;
 
 
 pub fn get_history(
+    source: &dyn ArtifactSource,
     artifact: &ArtifactConfig,
-    kind: &SourceType,
-    login: &Login,
     short: bool,
 ) -> Result<(), ErdError> {
-    match kind {
-        SourceType::Gitlab => get_history_gitlab(artifact, &login.password, short),
+    source.check_supported(Operation::History)?;
+    let builds = source.history(artifact)?;
+    let options = OutputOptions {
+        color: true,
+        short,
+    };
+    info!(
+        "Showing history for {} on branch {}",
+        artifact.id, artifact.branch
+    );
+    if short {
+        info!("Id - When - Commit (Author) - Status");
     }
-}
\ No newline at end of file
+    for build in builds {
+        let build_output = build.format_output(&options);
+        info!("{}", build_output);
+    }
+    Ok(())
+}
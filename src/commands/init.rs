@@ -19,10 +19,18 @@ pub fn init_erd(interactive: bool) -> Result<(), ErdError> {
     if !interactive {
         todo!();
     }
-    //println!("2) Github")
     let source_type: SourceType = loop {
-        println!("To get setup, lets add the first Repository Source (GitLab/GitHub)");
+        println!("To get setup, lets add the first Repository Source (GitLab/GitHub/Gitea)");
         println!(" - GitLab");
+        println!(" - GitHub");
+        println!(" - Gitea/Forgejo");
+        println!(" - Jenkins");
+        println!(" - HTTP");
+        println!(" - Local");
+        println!(" - S3");
+        println!(" - Maven");
+        println!(" - OCI");
+        println!(" - Command");
         let source_type_str = read_with_prompt("> ")?;
         let source_type = source_type_str.to_lowercase().parse();
         match source_type {
@@ -45,11 +53,94 @@ pub fn init_erd(interactive: bool) -> Result<(), ErdError> {
             }
             url
         }
+        SourceType::Github => {
+            println!("Custom GitHub URL? Leave blank for github.com");
+            let mut url = read_with_prompt("> ")?;
+            if url.is_empty() {
+                url = "https://github.com/".to_string();
+            }
+            url
+        }
+        SourceType::Gitea => loop {
+            println!("Gitea/Forgejo URL? (e.g. https://codeberg.org/)");
+            let url = read_with_prompt("> ")?;
+            if !url.is_empty() {
+                break url;
+            }
+        },
+        SourceType::Jenkins => loop {
+            println!("Jenkins URL? (e.g. https://ci.example.com/jenkins/)");
+            let url = read_with_prompt("> ")?;
+            if !url.is_empty() {
+                break url;
+            }
+        },
+        SourceType::Http => {
+            println!("Base URL? This replaces {{base}} in each artifact's url_template");
+            read_with_prompt("> ")?
+        }
+        SourceType::Local => loop {
+            println!("Directory containing the builds? (laid out as <project>/<branch>/<build-id>/)");
+            let path = read_with_prompt("> ")?;
+            if !path.is_empty() {
+                break path;
+            }
+        },
+        SourceType::S3 => loop {
+            println!("Endpoint and bucket? (e.g. https://minio.example.com/builds)");
+            let url = read_with_prompt("> ")?;
+            if !url.is_empty() {
+                break url;
+            }
+        },
+        SourceType::Maven => {
+            println!("Maven repository URL? Leave blank for Maven Central");
+            let mut url = read_with_prompt("> ")?;
+            if url.is_empty() {
+                url = "https://repo.maven.apache.org/maven2/".to_string();
+            }
+            url
+        }
+        SourceType::Command => {
+            println!("URL to pass to the command? (optional)");
+            read_with_prompt("> ")?
+        }
+        SourceType::Oci => loop {
+            println!("Registry URL? (e.g. https://registry.gitlab.com)");
+            let url = read_with_prompt("> ")?;
+            if !url.is_empty() {
+                break url;
+            }
+        },
+    };
+    let region = match source_type {
+        SourceType::S3 => {
+            println!("Region? Leave blank for us-east-1");
+            Some(read_with_prompt("> ")?).filter(|r| !r.is_empty())
+        }
+        _ => None,
+    };
+    let command = match source_type {
+        SourceType::Command => loop {
+            println!("Command to run, with its arguments? (e.g. erd-source-foo --verbose)");
+            let command: Vec<String> = read_with_prompt("> ")?
+                .split_whitespace()
+                .map(str::to_string)
+                .collect();
+            if !command.is_empty() {
+                break Some(command);
+            }
+        },
+        _ => None,
     };
     let id = format!("{:?}", source_type).to_lowercase();
     let source_config = SourceConfig {
         id: id.clone(),
         url,
+        region,
+        command,
+        retries: None,
+        http: None,
         kind: source_type,
         artifacts: vec![],
     };
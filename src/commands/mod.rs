@@ -1,4 +1,5 @@
 pub mod init;
 pub mod fetch;
 pub mod history;
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod cache;
\ No newline at end of file
@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
@@ -9,9 +10,25 @@ pub struct Config {
     pub sources: Vec<SourceConfig>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
 pub enum SourceType {
     Gitlab,
+    Github,
+    /// Gitea or Forgejo Actions
+    Gitea,
+    Jenkins,
+    /// Plain URLs built from each artifact's `url_template`
+    Http,
+    /// A local directory or network mount
+    Local,
+    /// An S3-compatible object storage bucket
+    S3,
+    /// A Maven repository, with artifacts identified by `groupId:artifactId`
+    Maven,
+    /// OCI artifacts in a container registry, with the file stored as a layer
+    Oci,
+    /// An external executable that erd talks to over JSON on stdin/stdout
+    Command,
 }
 
 impl FromStr for SourceType {
@@ -20,6 +37,15 @@ impl FromStr for SourceType {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "gitlab" => Ok(SourceType::Gitlab),
+            "github" => Ok(SourceType::Github),
+            "gitea" | "forgejo" => Ok(SourceType::Gitea),
+            "jenkins" => Ok(SourceType::Jenkins),
+            "http" => Ok(SourceType::Http),
+            "local" => Ok(SourceType::Local),
+            "s3" => Ok(SourceType::S3),
+            "maven" => Ok(SourceType::Maven),
+            "oci" => Ok(SourceType::Oci),
+            "command" => Ok(SourceType::Command),
             _ => Err(()),
         }
     }
@@ -34,24 +60,116 @@ pub struct SourceConfig {
     pub kind: SourceType,
     /// The main URL of the source.
     pub url: String,
+    /// The region to sign requests for, for S3-compatible sources
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// The executable and its arguments, for command sources
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<Vec<String>>,
+    /// How many times to retry requests that fail for reasons that may go away by themselves,
+    /// such as timeouts, `5xx` responses and rate limits. Defaults to 3
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// How to connect to the source, e.g. through a proxy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpConfig>,
     /// All artifacts that can be obtained from this source
     pub artifacts: Vec<ArtifactConfig>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+/// Settings for the HTTP client of a source
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+pub struct HttpConfig {
+    /// Seconds to wait for a connection to the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+    /// Seconds to wait for the server to send more of a response. Defaults to 30
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_timeout: Option<u64>,
+    /// The proxy to send all requests through, e.g. `http://proxy.internal:3128`.
+    /// Otherwise the `HTTP_PROXY`/`HTTPS_PROXY` environment variables are used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Hosts or domains to connect to directly rather than through `proxy`, e.g. `.internal`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub no_proxy: Vec<String>,
+    /// A PEM file of certificates to trust as well as the system's, for servers with a private CA
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<PathBuf>,
+    /// Skip verifying TLS certificates entirely. Prefer `ca_bundle` where possible
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub insecure: bool,
+    /// Sent as the `User-Agent` of every request, instead of `erd/<version>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
 pub struct ArtifactConfig {
     /// The human readable id of this artifact
     pub id: String,
     /// The project that this artifact can be retrieved from
     pub project_id: String,
-    /// The branch to retrieve the artifact from
+    /// The branch to retrieve the artifact from.
+    /// Not needed for channels that are not tied to a branch, such as releases.
+    /// Maven sources read it as the version instead: `latest`, `release` or an exact version
+    #[serde(default)]
     pub branch: String,
     /// The pattern in order to distiguish the artifact
     /// from other artifacts generated by both this project
-    /// and other projects:
+    /// and other projects. Either a glob matched against the file name
+    /// (or the whole path if it contains a `/`), a regex between slashes,
+    /// or a suffix that the path ends with:
     ///
-    /// Example: craftbook-extra
+    /// Example: craftbook-extra-*.jar, /craftbook-extra-[0-9.]+\.jar$/ or -extra.jar
     pub artifact_pattern: String,
+    /// Further patterns that also match the artifact
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Patterns for files that are never the artifact, e.g. `*-sources.jar`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Directories of the archive to install along with everything in them, e.g. `build/config`.
+    /// Each is installed under its own name, so `build/config` becomes `config/`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub directories: Vec<String>,
+    /// An archive within the downloaded archive to look for the artifact in,
+    /// e.g. `dist/*.tar.gz`. Uses the same pattern syntax as `artifact_pattern`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inner_archive: Option<String>,
+    /// Where to download the artifact from, for sources without an API.
+    /// `{base}`, `{project_id}`, `{branch}` and `{build_id}` are replaced,
+    /// e.g. `{base}/{project_id}/{branch}/latest.zip`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_template: Option<String>,
+    /// A URL listing the available build ids, one per line (or as a JSON array),
+    /// oldest first. Uses the same replacements as `url_template`, except `{build_id}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_url: Option<String>,
+    /// The CI job that produces the artifact, for GitLab sources. Defaults to `build`.
+    /// Either an exact job name, which also matches its parallel and matrix jobs
+    /// (e.g. `build` matches `build 1/2` and `build: [linux, 1.20]`),
+    /// a regex between slashes (e.g. `/^(package|assemble)$/`) or a stage (e.g. `stage:assemble`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<String>,
+    /// The exact path of the artifact within the job's artifacts, for GitLab sources,
+    /// e.g. `build/libs/plugin.jar`. When set only that file is downloaded, rather than
+    /// the whole artifacts archive. Ignored if `directories` or `inner_archive` are set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_path: Option<String>,
+    /// Where the artifact is published, for GitLab sources. Defaults to job artifacts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<Channel>,
+}
+
+/// Somewhere other than job artifacts that a GitLab project publishes its builds
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Channel {
+    /// A package in the project's generic package registry, with each version being a build
+    Package { name: String },
+    /// The project's releases, with each release's tag being a build
+    Release,
 }
 
 #[cfg(test)]
@@ -60,12 +178,12 @@ mod test {
 
     const BASIC_CONFIG: &str = r#"
     [[sources]]
-    name = "gitlab/tyhdefu"
+    id = "gitlab/tyhdefu"
     kind = "Gitlab"
     url = "https://gitlab.com"
 
     [[sources.artifacts]]
-    name = "Project1"
+    id = "Project1"
     project_id = "project-1"
     branch = "master"
     artifact_pattern = "*.jar"
@@ -80,11 +198,16 @@ mod test {
                 id: "gitlab/tyhdefu".into(),
                 kind: SourceType::Gitlab,
                 url: "https://gitlab.com".into(),
+                region: None,
+                command: None,
+                retries: None,
+                http: None,
                 artifacts: vec![ArtifactConfig {
                     id: "Project1".into(),
                     project_id: "project-1".into(),
                     branch: "master".into(),
                     artifact_pattern: "*.jar".into(),
+                    ..Default::default()
                 }],
             }],
         };
@@ -0,0 +1,420 @@
+//! Downloads are streamed into temporary files rather than held in memory,
+//! and hashed as they are written so that they never need to be read twice.
+//! Interrupted downloads are kept in `.erd/partial`, so that the next attempt can resume them.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{debug, info};
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+
+use crate::{config, ErdError};
+
+/// The most that [`Download::header`] will read, enough to find the magic of any archive format
+const HEADER_LEN: usize = 512;
+
+/// Writes through to a file, keeping a running SHA-256 of everything written
+struct HashingWriter<'a> {
+    file: &'a mut File,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl Write for HashingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// A downloaded file, stored in a temporary file until it is installed
+pub struct Download {
+    file: NamedTempFile,
+    sha256: Vec<u8>,
+    len: u64,
+}
+
+impl Download {
+    /// Stream everything from the reader into a new temporary file.
+    /// `what` describes the failure if reading or writing fails
+    pub fn from_reader(mut reader: impl Read, what: &str) -> Result<Self, ErdError> {
+        Self::from_writer(what, |writer| io::copy(&mut reader, writer).map(|_| ()))
+    }
+
+    /// Create a temporary file from whatever the given function writes
+    pub fn from_writer(
+        what: &str,
+        write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+    ) -> Result<Self, ErdError> {
+        let mut builder = tempfile::Builder::new();
+        builder.prefix("download-");
+        // Temporary files are only readable by their owner by default,
+        // but these are installed as they are so should have the usual permissions
+        #[cfg(unix)]
+        builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+        let mut file = builder
+            .tempfile_in(get_temp_dir())
+            .map_err(|e| ErdError::IOError(e, "Failed to create temporary file".to_string()))?;
+        let mut writer = HashingWriter {
+            file: file.as_file_mut(),
+            hasher: Sha256::new(),
+            len: 0,
+        };
+        write(&mut writer).map_err(|e| ErdError::IOError(e, what.to_string()))?;
+        let sha256 = writer.hasher.finalize().to_vec();
+        let len = writer.len;
+        Ok(Self { file, sha256, len })
+    }
+
+    pub fn sha256(&self) -> &[u8] {
+        &self.sha256
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Open the file for reading from the start
+    pub fn open(&self) -> Result<File, ErdError> {
+        self.file
+            .reopen()
+            .map_err(|e| ErdError::IOError(e, "Failed to open downloaded file".to_string()))
+    }
+
+    /// Read the start of the file, such as to detect its format
+    pub fn header(&self) -> Result<Vec<u8>, ErdError> {
+        let mut header = vec![];
+        self.open()?
+            .take(HEADER_LEN as u64)
+            .read_to_end(&mut header)
+            .map_err(|e| ErdError::IOError(e, "Failed to read downloaded file".to_string()))?;
+        Ok(header)
+    }
+
+    /// Move the file to the given path, replacing anything already there.
+    /// Falls back to copying if the path is on another filesystem
+    pub fn persist(self, path: &Path) -> Result<(), ErdError> {
+        let error = match self.file.persist(path) {
+            Ok(_) => return Ok(()),
+            Err(error) => error,
+        };
+        let mut source = error
+            .file
+            .reopen()
+            .map_err(|e| ErdError::IOError(e, "Failed to open downloaded file".to_string()))?;
+        let mut output = File::create(path)
+            .map_err(|e| ErdError::IOError(e, "Failed to create Artifact file".to_string()))?;
+        io::copy(&mut source, &mut output)
+            .map_err(|e| ErdError::IOError(e, "Failed to write Artifact".to_string()))?;
+        Ok(())
+    }
+
+    /// Read the whole file into memory, for comparing in tests
+    #[cfg(test)]
+    pub fn to_vec(&self) -> Vec<u8> {
+        fs::read(self.file.path()).expect("Failed to read downloaded file")
+    }
+}
+
+/// Downloads go in erd's local directory when there is one, so that they can be moved into place
+/// rather than copied, and do not end up in a RAM-backed `/tmp`
+fn get_temp_dir() -> PathBuf {
+    let local_dir = config::get_local_dir();
+    if local_dir.is_dir() {
+        let temp_dir = local_dir.join("tmp");
+        if fs::create_dir_all(&temp_dir).is_ok() {
+            return temp_dir;
+        }
+    }
+    std::env::temp_dir()
+}
+
+/// The partial downloads currently being written to
+static IN_PROGRESS: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+
+/// Partial downloads are only kept when erd has a local directory to keep them in
+fn get_partial_dir() -> Option<PathBuf> {
+    let local_dir = config::get_local_dir();
+    if !local_dir.is_dir() {
+        return None;
+    }
+    let partial_dir = local_dir.join("partial");
+    fs::create_dir_all(&partial_dir).ok()?;
+    Some(partial_dir)
+}
+
+/// What is known about a partial download, to check that the file has not changed before resuming it
+#[derive(Serialize, Deserialize)]
+struct PartialInfo {
+    url: String,
+    /// The `ETag` of the file, or its `Last-Modified` if it has no strong `ETag`
+    validator: String,
+    /// The full size of the file, if the server gave it
+    total: Option<u64>,
+}
+
+/// A download that carries on from where an earlier, interrupted, attempt got to
+pub struct Resumable {
+    url: String,
+    /// Where the partial download is kept, or None if it cannot be resumed
+    path: Option<PathBuf>,
+    /// How much of the file was downloaded before, if resuming
+    offset: u64,
+    /// What the file was when it was partially downloaded, if resuming
+    validator: Option<String>,
+}
+
+impl Resumable {
+    /// Prepare a request for a file, asking for just the rest of it if it was partially downloaded before
+    pub fn prepare(request: RequestBuilder) -> (RequestBuilder, Resumable) {
+        let url = request
+            .try_clone()
+            .and_then(|r| r.build().ok())
+            .map(|r| r.url().to_string())
+            .unwrap_or_default();
+        let resumable = Resumable::new(&url);
+        (request.headers(resumable.headers()), resumable)
+    }
+
+    /// Look for a partial download of the given URL
+    pub fn new(url: &str) -> Self {
+        Self::new_in(get_partial_dir(), url)
+    }
+
+    fn new_in(dir: Option<PathBuf>, url: &str) -> Self {
+        let path = dir
+            .filter(|_| !url.is_empty())
+            .map(|dir| dir.join(format!("{:x}", Sha256::digest(url))));
+        // Artifacts fetched at the same time can come from the same file, such as a job's artifacts,
+        // in which case only the first download gets to keep a partial file
+        let mut in_progress = IN_PROGRESS.lock().expect("In progress lock was poisoned");
+        let path = path.filter(|p| !in_progress.contains(p));
+        in_progress.extend(path.clone());
+        drop(in_progress);
+        let mut resumable = Resumable {
+            url: url.to_string(),
+            path,
+            offset: 0,
+            validator: None,
+        };
+        let Some(info) = resumable.read_info() else {
+            return resumable;
+        };
+        let offset = resumable
+            .path
+            .as_ref()
+            .and_then(|p| fs::metadata(p).ok())
+            .map_or(0, |m| m.len());
+        // A complete file would not have been left behind, unless erd was stopped just as it finished
+        if offset > 0 && info.total.is_none_or(|total| offset < total) {
+            debug!("Asking to resume {} from {} bytes", url, offset);
+            resumable.offset = offset;
+            resumable.validator = Some(info.validator);
+        }
+        resumable
+    }
+
+    /// The headers to add to the request for the file.
+    /// `If-Range` is sent along with the `Range`, so that the server sends the whole file if it has changed
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let validator = self.validator.as_deref().and_then(|v| HeaderValue::from_str(v).ok());
+        if let Some(validator) = validator {
+            let range = format!("bytes={}-", self.offset);
+            headers.insert(RANGE, HeaderValue::from_str(&range).expect("Ranges are valid headers"));
+            headers.insert(IF_RANGE, validator);
+        }
+        headers
+    }
+
+    fn info_path(&self) -> Option<PathBuf> {
+        self.path.as_ref().map(|p| p.with_extension("json"))
+    }
+
+    fn read_info(&self) -> Option<PartialInfo> {
+        let data = fs::read(self.info_path()?).ok()?;
+        let info: PartialInfo = serde_json::from_slice(&data).ok()?;
+        // Guard against the (unlikely) case of two URLs with the same hash
+        (info.url == self.url).then_some(info)
+    }
+
+    /// Download the response to the prepared request, carrying on from the partial download
+    /// if the server only sent the rest of the file. If the download is interrupted,
+    /// what was downloaded is kept for next time
+    pub fn finish(self, mut response: Response, what: &str) -> Result<Download, ErdError> {
+        let (Some(path), Some(info_path)) = (self.path.clone(), self.info_path()) else {
+            return Download::from_reader(response, what);
+        };
+        // Without an ETag or Last-Modified there is no way to tell if the file changes, so it is not safe to resume
+        let Some(validator) = get_validator(response.headers()) else {
+            let _ = fs::remove_file(&info_path);
+            return Download::from_reader(response, what);
+        };
+        let content_range = match response.status() {
+            StatusCode::PARTIAL_CONTENT => parse_content_range(response.headers()),
+            _ => None,
+        };
+        let resumed = self.offset > 0 && content_range.is_some_and(|(start, _)| start == self.offset);
+        if content_range.is_some() && !resumed {
+            let _ = fs::remove_file(&info_path);
+            return Err(ErdError::IOError(
+                io::Error::new(io::ErrorKind::InvalidData, "server sent a different range than was asked for"),
+                what.to_string(),
+            ));
+        }
+        if resumed {
+            info!("Resuming download from {} bytes", self.offset);
+        } else if self.offset > 0 {
+            info!("{} has changed since it was partially downloaded, starting again", self.url);
+        }
+        let info = PartialInfo {
+            url: self.url.clone(),
+            validator,
+            total: match resumed {
+                true => content_range.and_then(|(_, total)| total),
+                false => response.content_length(),
+            },
+        };
+        fs::write(&info_path, serde_json::to_vec(&info).expect("Partial info should always serialize"))
+            .map_err(|e| ErdError::IOError(e, "Failed to save partial download".to_string()))?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&path)
+            .map_err(|e| ErdError::IOError(e, "Failed to create partial download".to_string()))?;
+        let mut hasher = Sha256::new();
+        if resumed {
+            let mut partial = File::open(&path)
+                .map_err(|e| ErdError::IOError(e, "Failed to read partial download".to_string()))?;
+            io::copy(&mut partial, &mut hasher)
+                .map_err(|e| ErdError::IOError(e, "Failed to read partial download".to_string()))?;
+        }
+        let mut writer = HashingWriter {
+            file: &mut file,
+            hasher,
+            len: if resumed { self.offset } else { 0 },
+        };
+        io::copy(&mut response, &mut writer).map_err(|e| ErdError::IOError(e, what.to_string()))?;
+        let sha256 = writer.hasher.finalize().to_vec();
+        let len = writer.len;
+
+        // The download is complete, so move it out of the way of the next download of the same URL
+        let _ = fs::remove_file(&info_path);
+        let temp_path = NamedTempFile::new_in(get_temp_dir())
+            .map_err(|e| ErdError::IOError(e, "Failed to create temporary file".to_string()))?
+            .into_temp_path();
+        fs::rename(&path, &temp_path)
+            .map_err(|e| ErdError::IOError(e, "Failed to move partial download".to_string()))?;
+        Ok(Download {
+            file: NamedTempFile::from_parts(file, temp_path),
+            sha256,
+            len,
+        })
+    }
+}
+
+impl Drop for Resumable {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            IN_PROGRESS
+                .lock()
+                .expect("In progress lock was poisoned")
+                .retain(|p| p != path);
+        }
+    }
+}
+
+/// Get what to check that a file is unchanged with, in `If-Range`. Weak ETags cannot be used
+fn get_validator(headers: &HeaderMap) -> Option<String> {
+    let etag = headers
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .filter(|etag| !etag.starts_with("W/"));
+    etag.or_else(|| headers.get(LAST_MODIFIED)?.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Parse `Content-Range: bytes <start>-<end>/<total>` into the start and total
+fn parse_content_range(headers: &HeaderMap) -> Option<(u64, Option<u64>)> {
+    let range = headers.get(CONTENT_RANGE)?.to_str().ok()?.strip_prefix("bytes ")?;
+    let (range, total) = range.split_once('/')?;
+    let (start, _end) = range.split_once('-')?;
+    Some((start.parse().ok()?, total.parse().ok()))
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::blocking::Client;
+
+    use super::*;
+    use crate::sources::test_server::{serve, Route};
+
+    #[test]
+    fn test_download_and_persist() {
+        let data = b"modpack contents".repeat(10_000);
+        let download = Download::from_reader(data.as_slice(), "Failed to download").unwrap();
+        assert_eq!(download.len(), data.len() as u64);
+        assert_eq!(download.sha256(), Sha256::digest(&data).as_slice());
+        assert_eq!(download.header().unwrap(), &data[..HEADER_LEN]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("modpack.zip");
+        download.persist(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+
+    #[test]
+    fn test_resume() {
+        let data = b"modpack contents".repeat(10_000);
+        let url = serve(vec![Route::new("/modpack.zip", data.clone()).with_etag("\"v2\"")]);
+        let url = format!("{}/modpack.zip", url);
+        let dir = tempfile::tempdir().unwrap();
+        let client = Client::new();
+        // Leave behind a partial download, as if the connection dropped part way through
+        let leave_partial = |contents: &[u8], validator: &str| {
+            let resumable = Resumable::new_in(Some(dir.path().into()), &url);
+            fs::write(resumable.path.as_ref().unwrap(), contents).unwrap();
+            let info = PartialInfo {
+                url: url.clone(),
+                validator: validator.to_string(),
+                total: Some(data.len() as u64),
+            };
+            fs::write(resumable.info_path().unwrap(), serde_json::to_vec(&info).unwrap()).unwrap();
+        };
+
+        leave_partial(&data[..1000], "\"v2\"");
+        let resumable = Resumable::new_in(Some(dir.path().into()), &url);
+        let response = client.get(&url).headers(resumable.headers()).send().unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let download = resumable.finish(response, "Failed to download").unwrap();
+        assert_eq!(download.to_vec(), data);
+        assert_eq!(download.sha256(), Sha256::digest(&data).as_slice());
+        drop(download);
+
+        // The file changed since the partial download, so it is downloaded again in full
+        leave_partial(b"old contents", "\"v1\"");
+        let resumable = Resumable::new_in(Some(dir.path().into()), &url);
+        let response = client.get(&url).headers(resumable.headers()).send().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let download = resumable.finish(response, "Failed to download").unwrap();
+        assert_eq!(download.to_vec(), data);
+        drop(download);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -69,7 +69,7 @@ impl Logins {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[derive(Serialize, Deserialize)]
 pub struct Login {
     pub url: String,
@@ -1,13 +1,17 @@
-mod gitlab;
+mod archive;
+mod cache;
+mod download;
 mod log;
 mod input;
 mod output;
+mod pattern;
 mod config;
 mod logins;
 mod commands;
+mod sources;
 
 use std::fs;
-use std::io::{self, Read, Seek};
+use std::io;
 use std::path::{Path, PathBuf};
 use std::{fmt::Display, process::exit};
 
@@ -16,16 +20,44 @@ use logins::Login;
 use input::read_with_prompt;
 use ::log::{error, info, LevelFilter};
 use clap::{Parser, Subcommand};
-use gitlab::{get_history_gitlab, rebuild_artifact_gitlab, scan_gitlab};
-use output::{ArtifactListOutput, FormatOutput, OutputOptions};
+use output::{ArtifactListOutput, FormatOutput, OutputOptions, ScanProjectsOutput};
+use sources::{ArtifactSource, Operation};
 use sha2::{Digest, Sha256};
-use zip::ZipArchive;
 
-use config::artifacts::{Config, ArtifactConfig, SourceConfig, SourceType};
+use config::artifacts::{Config, ArtifactConfig, Channel, SourceConfig, SourceType};
 
 pub struct FileData {
+    /// The path to install the file at, relative to the download directory
     file_name: PathBuf,
-    data: Vec<u8>,
+    data: download::Download,
+    /// Unix permission bits, if the source has them
+    mode: Option<u32>,
+}
+
+/// All of the files that make up an artifact
+pub struct ArtifactFiles {
+    files: Vec<FileData>,
+}
+
+impl ArtifactFiles {
+    /// A short description of the files, for showing to the user
+    pub fn describe(&self) -> String {
+        let first = self
+            .files
+            .first()
+            .map(|f| f.file_name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        match self.files.len() {
+            1 => first,
+            n => format!("{} and {} other files", first, n - 1),
+        }
+    }
+}
+
+impl From<FileData> for ArtifactFiles {
+    fn from(file: FileData) -> Self {
+        ArtifactFiles { files: vec![file] }
+    }
 }
 
 #[derive(Debug)]
@@ -42,6 +74,68 @@ pub enum ErdError {
     NoLogin {
         source_url: String,
     },
+    /// The source rejected the login, or needed one and there was none
+    Unauthorized {
+        source_url: String,
+    },
+    /// The login is not allowed to access the requested resource
+    Forbidden {
+        source_url: String,
+        url: String,
+    },
+    /// The project, branch or build does not exist, or is private and needs a login
+    NotFound {
+        source: SourceType,
+        url: String,
+    },
+    /// The build exists, but its artifacts have expired or been deleted
+    ArtifactExpired {
+        artifact: String,
+        build_id: String,
+    },
+    /// No job in the latest pipelines of the artifact's branch succeeded with artifacts
+    NoSuccessfulJob {
+        artifact: String,
+        branch: String,
+        /// How many pipelines were searched
+        searched: usize,
+    },
+    /// The source's configuration is invalid
+    InvalidSourceConfig {
+        source: String,
+        desc: String,
+    },
+    /// The artifact's configuration is missing something its source needs
+    InvalidArtifactConfig {
+        artifact: String,
+        desc: String,
+    },
+    /// The source does not support the requested operation
+    Unsupported {
+        source: SourceType,
+        operation: Operation,
+    },
+    /// An external command source failed or gave an invalid response
+    CommandFailed {
+        command: String,
+        desc: String,
+    },
+    /// Several different files matched the artifact's patterns
+    AmbiguousArtifact {
+        artifact: String,
+        candidates: Vec<String>,
+    },
+    /// Some of the artifacts being fetched could not be
+    FetchFailed {
+        failed: usize,
+        total: usize,
+    },
+    /// A downloaded file did not match the checksum published for it
+    ChecksumMismatch {
+        file: String,
+        expected: String,
+        actual: String,
+    },
     IOError(io::Error, String),
     /// Failed to deserialize config
     Deserialize(toml::de::Error, String),
@@ -60,6 +154,54 @@ impl Display for ErdError {
             ErdError::InvalidToken(token) => write!(f, "Token was invalid: '{}'", token),
             ErdError::IOError(err, desc) => write!(f, "{desc}: {err}"),
             ErdError::NoLogin { source_url } => write!(f, "Missing login for {}", source_url),
+            ErdError::Unauthorized { source_url } => write!(
+                f,
+                "Not authorized by {}, the login is missing or has expired. Run `erd auth {}` to log in",
+                source_url, source_url
+            ),
+            ErdError::Forbidden { source_url, url } => write!(
+                f,
+                "Access to {} was denied. Check that the login for {} can read the project, or run `erd auth {}` with one that can",
+                url, source_url, source_url
+            ),
+            ErdError::NotFound { source, url } => write!(
+                f,
+                "{} was not found on {:?}. Check the project_id and branch of the artifact, or run `erd auth` if the project is private",
+                url, source
+            ),
+            ErdError::ArtifactExpired { artifact, build_id } => write!(
+                f,
+                "Artifacts of build {} of '{}' have expired, try `erd rebuild {} {}`",
+                build_id, artifact, artifact, build_id
+            ),
+            ErdError::NoSuccessfulJob { artifact, branch, searched } => write!(
+                f,
+                "No successful job with artifacts for '{}' in the latest {} pipelines of branch '{}'. Check the branch and job of the artifact, or run a pipeline",
+                artifact, searched, branch
+            ),
+            ErdError::InvalidSourceConfig { source, desc } => {
+                write!(f, "Invalid configuration for source '{}': {}", source, desc)
+            }
+            ErdError::InvalidArtifactConfig { artifact, desc } => {
+                write!(f, "Invalid configuration for artifact '{}': {}", artifact, desc)
+            }
+            ErdError::Unsupported { source, operation } => {
+                write!(f, "{} not supported by this source ({:?})", operation, source)
+            }
+            ErdError::CommandFailed { command, desc } => write!(f, "Command '{}' failed: {}", command, desc),
+            ErdError::AmbiguousArtifact { artifact, candidates } => {
+                write!(f, "Pattern for artifact '{}' matches several files, make it more specific or add an exclude:", artifact)?;
+                for candidate in candidates {
+                    write!(f, "\n - {}", candidate)?;
+                }
+                Ok(())
+            }
+            ErdError::FetchFailed { failed, total } => {
+                write!(f, "Failed to fetch {} of {} artifacts", failed, total)
+            }
+            ErdError::ChecksumMismatch { file, expected, actual } => {
+                write!(f, "Checksum of {} did not match: expected {}, got {}", file, expected, actual)
+            }
             ErdError::Deserialize(e, desc) => write!(f, "Failed to deserialize: {}. {}", desc, e),
             ErdError::Serialize(e, desc) => write!(f, "Failed to Serialize: {}. {}", desc, e),
         }
@@ -74,16 +216,6 @@ fn get_artifact_config_file(specified: &Option<PathBuf>) -> PathBuf {
     })
 }
 
-fn get_store_directory(specified: &Option<PathBuf>) -> PathBuf {
-    if let Some(s) = specified {
-        return s.clone();
-    }
-    if cfg!(debug_assertions) {
-        return "temp".into();
-    }
-    return config::get_local_dir();
-}
-
 fn main() {
     let cli = Cli::parse();
 
@@ -136,9 +268,10 @@ fn handle_cli(cli: Cli, config: Config, config_file_path: &Path, options: Output
     match cli.command {
         // TODO: split into multiple but hide from clap - clap(flatten)
         Commands::Init { .. } => panic!("Init should have already been handled!"),
-        Commands::Fetch { artifact, build_id } => {
+        Commands::Fetch { artifact, build_id, jobs } => {
             let logins = logins::read_logins_file(&auth_file)?;
-            return commands::fetch::fetch(&config, &logins, artifact, build_id, &options)
+            let destination = commands::fetch::Destination::local();
+            return commands::fetch::fetch(&config, &logins, artifact, build_id, jobs, &destination, &options)
         }
         Commands::Scan {
             source,
@@ -172,9 +305,8 @@ fn handle_cli(cli: Cli, config: Config, config_file_path: &Path, options: Output
             });
             let (src, a) = found.ok_or(ErdError::NoSuchArtifact(artifact))?;
             let logins = logins::read_logins_file(&auth_file)?;
-            let login = logins.find_login(&src.url)
-                .ok_or_else(|| ErdError::NoLogin { source_url: src.url.clone() })?;
-            commands::history::get_history(a, &src.kind, login, short)?;
+            let artifact_source = sources::create_source(src, logins.find_login(&src.url))?;
+            commands::history::get_history(&*artifact_source, a, short)?;
         }
         Commands::List { source } => {
             list_artifacts(&config, source.clone())?;
@@ -188,10 +320,13 @@ fn handle_cli(cli: Cli, config: Config, config_file_path: &Path, options: Output
             });
             let (src, a) = found.ok_or(ErdError::NoSuchArtifact(artifact))?;
             let logins = logins::read_logins_file(&auth_file)?;
-            let login = logins.find_login(&src.url)
-                .ok_or_else(|| ErdError::NoLogin { source_url: src.url.clone() })?;
-            rebuild_artifact(a, &src.kind, &login.password, build_id)?;
+            let artifact_source = sources::create_source(src, logins.find_login(&src.url))?;
+            rebuild_artifact(&*artifact_source, a, build_id)?;
         }
+        Commands::Cache { command } => match command {
+            CacheCommands::List => commands::cache::list_cache(&options)?,
+            CacheCommands::Prune { max_age, max_size } => commands::cache::prune_cache(max_age, max_size)?,
+        },
         Commands::Add { source, project_id } => {
             let mut new_config = config.clone();
             let source = new_config
@@ -201,14 +336,44 @@ fn handle_cli(cli: Cli, config: Config, config_file_path: &Path, options: Output
                 .ok_or(ErdError::NoSuchSource(source))?;
 
             let id = read_with_prompt("Unique ID")?;
-            let branch = read_with_prompt("Branch")?;
-            let artifact_pattern = read_with_prompt("Artifact Pattern (e.g. *.jar)")?;
-            let art = ArtifactConfig {
+            let (branch, artifact_pattern) = match source.kind {
+                SourceType::Maven => (
+                    read_with_prompt("Version (latest, release or a version)")?,
+                    read_with_prompt("Classifier and Extension (e.g. .jar or -all.jar)")?,
+                ),
+                _ => (
+                    read_with_prompt("Branch")?,
+                    read_with_prompt("Artifact Pattern (e.g. *.jar)")?,
+                ),
+            };
+            let mut art = ArtifactConfig {
                 id,
                 project_id,
                 branch,
                 artifact_pattern,
+                ..Default::default()
             };
+            if source.kind == SourceType::Http {
+                art.url_template = Some(read_with_prompt("URL Template (e.g. {base}/{project_id}/{branch}/latest.zip)")?);
+                let index_url = read_with_prompt("Index URL (optional)")?;
+                art.index_url = Some(index_url).filter(|u| !u.is_empty());
+            }
+            if source.kind == SourceType::Gitlab {
+                let channel = read_with_prompt("Track job artifacts, a generic package or releases? (jobs/package/release)")?;
+                match channel.to_lowercase().as_str() {
+                    "package" => {
+                        let name = read_with_prompt("Generic package name")?;
+                        art.channel = Some(Channel::Package { name });
+                    }
+                    "release" => art.channel = Some(Channel::Release),
+                    _ => {
+                        let job = read_with_prompt("CI job, /regex/ or stage:<name> (leave blank for build)")?;
+                        art.job = Some(job).filter(|j| !j.is_empty());
+                        let path = read_with_prompt("Path of the artifact in the job's artifacts (optional, e.g. build/libs/plugin.jar)")?;
+                        art.artifact_path = Some(path).filter(|p| !p.is_empty());
+                    }
+                }
+            }
             source.artifacts.push(art);
             let config_str = toml::to_string(&new_config).expect("Should be able to serialize");
             std::fs::write(config_file_path, config_str)
@@ -233,6 +398,9 @@ enum Commands {
         artifact: Option<String>,
         /// Fetch a specific version rather than the latest
         build_id: Option<String>,
+        /// How many artifacts to fetch at once, when fetching all of them
+        #[clap(short, long, default_value_t = 4)]
+        jobs: usize,
     },
     /// Scan for projects to add to configuration
     Scan {
@@ -272,6 +440,26 @@ enum Commands {
         /// The ID of the project to be added
         project_id: String,
     }, // TODO: Perhaps a way to tag versions before rebuilding?
+    /// Manage the cache of fetched builds, in .erd/cache or the directory given by ERD_CACHE_DIR
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommands {
+    /// List the cached builds
+    List,
+    /// Remove cached builds. With no limits, everything is removed
+    Prune {
+        /// Remove builds that have not been used for this many days
+        #[clap(long)]
+        max_age: Option<u64>,
+        /// Remove the least recently used builds until the cache is at most this big, e.g. 500M or 2G
+        #[clap(long, value_parser = cache::parse_size)]
+        max_size: Option<u64>,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -287,9 +475,19 @@ struct Cli {
 }
 
 fn scan_source(source: &SourceConfig, group: Option<String>, login: Option<&Login>) -> Result<(), ErdError> {
-    match source.kind {
-        SourceType::Gitlab => scan_gitlab(group, login.map(|l| &*l.password)),
-    }
+    let artifact_source = sources::create_source(source, login)?;
+    artifact_source.check_supported(Operation::Scan)?;
+    let projects = artifact_source.scan(group)?;
+    let options = OutputOptions {
+        color: true,
+        short: false,
+    };
+    let projects_output = ScanProjectsOutput {
+        projects,
+        options,
+    };
+    info!("{}", projects_output);
+    Ok(())
 }
 
 fn list_artifacts(config: &Config, source: Option<String>) -> Result<(), ErdError> {
@@ -318,30 +516,20 @@ fn list_artifacts(config: &Config, source: Option<String>) -> Result<(), ErdErro
 }
 
 fn rebuild_artifact(
+    source: &dyn ArtifactSource,
     artifact: &ArtifactConfig,
-    kind: &SourceType,
-    token: &str,
     build_id: String,
 ) -> Result<(), ErdError> {
-    match kind {
-        SourceType::Gitlab => rebuild_artifact_gitlab(artifact, token, build_id),
-    }
+    source.check_supported(Operation::Rebuild)?;
+    source.rebuild(artifact, build_id)
 }
 
-pub fn extract_file(
-    archive: &mut ZipArchive<impl Read + Seek>,
-    file: &str,
-) -> Result<FileData, io::Error> {
-    let path: PathBuf = file.parse().expect("Invalid filename");
-    let file_name = path.file_name().expect("Could not get filename from path");
-
-    let mut jar = archive.by_name(file)?;
-    let mut file_buf = vec![];
-    jar.read_to_end(&mut file_buf)?;
-    Ok(FileData {
-        file_name: file_name.into(),
-        data: file_buf,
-    })
+/// Format a unix timestamp (in seconds) in the same RFC 3339 form that APIs report
+pub fn format_timestamp(unix_seconds: i64) -> String {
+    time::OffsetDateTime::from_unix_timestamp(unix_seconds)
+        .ok()
+        .and_then(|t| t.format(&time::format_description::well_known::Rfc3339).ok())
+        .unwrap_or_else(|| unix_seconds.to_string())
 }
 
 fn sha256sum_file(path: &Path) -> Result<Vec<u8>, io::Error> {
@@ -350,9 +538,3 @@ fn sha256sum_file(path: &Path) -> Result<Vec<u8>, io::Error> {
     io::copy(&mut file, &mut hasher)?;
     Ok(hasher.finalize().iter().cloned().collect())
 }
-
-fn sha256sum_mem(data: &FileData) -> Result<Vec<u8>, io::Error> {
-    let hash = Sha256::digest(&data.data);
-    Ok(hash.iter().cloned().collect())
-}
-
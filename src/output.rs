@@ -4,8 +4,10 @@ use std::io::{self, Write};
 use log::error;
 use termcolor::{Buffer, Color, ColorSpec, WriteColor};
 
+use crate::cache::CachedBuild;
 use crate::config::artifacts::SourceConfig;
 use crate::commands::fetch::GetArtifactAnswer;
+use crate::sources::BuildInfo;
 
 /// Describes how output should be formatted
 #[derive(Debug, Clone)]
@@ -38,6 +40,7 @@ pub struct JobHistoryOutput {
     pub commit_short_id: String,
     pub commit_title: String,
     pub commit_author: String,
+    pub notes: String,
     pub options: OutputOptions,
 }
 
@@ -46,6 +49,8 @@ impl JobHistoryOutput {
         // TODO: Convert status to enum in display
         match (&*self.status, self.has_artifacts) {
             ("success", true) => Color::Green,
+            ("available", true) => Color::Green,
+            ("released", true) => Color::Green,
             ("success", false) => Color::Yellow,
             ("failed", false) => Color::Red,
             _ => Color::Yellow,
@@ -94,7 +99,14 @@ impl JobHistoryOutput {
         )?;
         buf.reset()?;
         writeln!(buf, "\tURL: {}", self.web_url)?;
-        writeln!(buf, "\tAuthor: {}", self.commit_author)
+        writeln!(buf, "\tAuthor: {}", self.commit_author)?;
+        if !self.notes.is_empty() {
+            writeln!(buf, "\tNotes:")?;
+            for line in self.notes.lines() {
+                writeln!(buf, "\t\t{}", line)?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -116,6 +128,24 @@ impl Display for JobHistoryOutput {
     }
 }
 
+impl FormatOutput<JobHistoryOutput> for BuildInfo {
+    fn format_output(self, options: &OutputOptions) -> JobHistoryOutput {
+        JobHistoryOutput {
+            id: self.id,
+            job_ref: self.job_ref,
+            timestamp: self.timestamp,
+            status: self.status,
+            has_artifacts: self.has_artifacts,
+            web_url: self.web_url,
+            commit_short_id: self.commit_short_id,
+            commit_title: self.commit_title,
+            commit_author: self.commit_author,
+            notes: self.notes,
+            options: options.clone(),
+        }
+    }
+}
+
 pub struct ArtifactListOutput<'a> {
     source: &'a SourceConfig,
     options: OutputOptions,
@@ -213,6 +243,65 @@ impl Display for ScanProjectsOutput {
     }
 }
 
+pub struct CacheListOutput {
+    pub builds: Vec<CachedBuild>,
+    /// The current time, in seconds since the unix epoch
+    pub now: u64,
+    pub options: OutputOptions,
+}
+
+impl CacheListOutput {
+    fn fmt_default(&self, buf: &mut Buffer) -> Result<(), io::Error> {
+        let total: u64 = self.builds.iter().map(|b| b.size()).sum();
+        writeln!(buf, "== {} cached builds ({}) ==", self.builds.len(), format_size(total))?;
+        for build in &self.builds {
+            write!(buf, "- ")?;
+            buf.set_color(ColorSpec::new().set_fg(Some(ARTIFACT_ID_COLOR)))?;
+            write!(buf, "{}", build.artifact)?;
+            buf.reset()?;
+            write!(buf, " {} from ", build.build_id)?;
+            buf.set_color(ColorSpec::new().set_fg(Some(SOURCE_ID_COLOR)))?;
+            write!(buf, "{}", build.source)?;
+            buf.reset()?;
+            let days = self.now.saturating_sub(build.last_used) / (24 * 60 * 60);
+            let used = match days {
+                0 => "today".to_string(),
+                1 => "yesterday".to_string(),
+                n => format!("{} days ago", n),
+            };
+            writeln!(buf, " - {} files, {} - used {}", build.files.len(), format_size(build.size()), used)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for CacheListOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buf = create_buf(&self.options);
+        self.fmt_default(&mut buf).map_err(|e| {
+            error!("Failed to format CacheListOutput: {}", e);
+            fmt::Error
+        })?;
+        let s = buf_to_str(buf)?;
+        write!(f, "{}", s)
+    }
+}
+
+/// Format a number of bytes for people, e.g. `1.5 MiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
 pub struct GetArtifactAnswerOutput {
     answer: GetArtifactAnswer,
     options: OutputOptions,
@@ -221,13 +310,18 @@ pub struct GetArtifactAnswerOutput {
 impl GetArtifactAnswerOutput {
     fn fmt_default(&self, buf: &mut Buffer) -> Result<(), io::Error> {
         let color = match self.answer {
-            GetArtifactAnswer::NotFound => Color::Red,
+            GetArtifactAnswer::NotFound | GetArtifactAnswer::Failed(_) => Color::Red,
             GetArtifactAnswer::NewArtifact(_) => Color::Green,
             GetArtifactAnswer::UpToDate(_) => Color::Yellow,
         };
         buf.set_color(ColorSpec::new().set_fg(Some(color)))?;
         match &self.answer {
             GetArtifactAnswer::NotFound => write!(buf, "Not Found!")?,
+            GetArtifactAnswer::Failed(e) => {
+                write!(buf, "Failed ")?;
+                buf.reset()?;
+                write!(buf, "({})", e)?
+            }
             GetArtifactAnswer::NewArtifact(file) => {
                 write!(buf, "New ")?;
                 buf.reset()?;
@@ -0,0 +1,157 @@
+use globset::{Glob, GlobBuilder, GlobMatcher};
+use regex::Regex;
+
+use crate::config::artifacts::ArtifactConfig;
+use crate::ErdError;
+
+/// A single pattern from an artifact's configuration
+enum Pattern {
+    /// `/regex/`, matched against the whole path
+    Regex(Regex),
+    /// A glob such as `*.jar`, matched against the file name,
+    /// or against the whole path if the glob contains a `/`
+    Glob { matcher: GlobMatcher, whole_path: bool },
+    /// Any other pattern matches paths ending with it, e.g. `.jar` or `craftbook-extra.jar`
+    Suffix(String),
+}
+
+impl Pattern {
+    fn parse(artifact: &ArtifactConfig, pattern: &str) -> Result<Self, ErdError> {
+        let invalid = |desc: String| ErdError::InvalidArtifactConfig {
+            artifact: artifact.id.clone(),
+            desc,
+        };
+        if let Some(regex) = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            return Regex::new(regex)
+                .map(Pattern::Regex)
+                .map_err(|e| invalid(format!("Invalid pattern regex '{}': {}", pattern, e)));
+        }
+        if pattern.contains(['*', '?', '[', '{']) {
+            let whole_path = pattern.contains('/');
+            let glob = match whole_path {
+                true => GlobBuilder::new(pattern).literal_separator(true).build(),
+                false => Glob::new(pattern),
+            }
+            .map_err(|e| invalid(format!("Invalid pattern glob '{}': {}", pattern, e)))?;
+            return Ok(Pattern::Glob {
+                matcher: glob.compile_matcher(),
+                whole_path,
+            });
+        }
+        Ok(Pattern::Suffix(pattern.to_string()))
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        match self {
+            Pattern::Regex(regex) => regex.is_match(path),
+            Pattern::Glob { matcher, whole_path: true } => matcher.is_match(path),
+            Pattern::Glob { matcher, whole_path: false } => {
+                matcher.is_match(path.rsplit(['/', '\\']).next().unwrap_or(path))
+            }
+            Pattern::Suffix(suffix) => path.ends_with(suffix),
+        }
+    }
+}
+
+/// Decides which files are the artifact, from its `artifact_pattern`, `include` and `exclude` patterns
+pub struct ArtifactMatcher {
+    artifact: String,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl ArtifactMatcher {
+    pub fn new(artifact: &ArtifactConfig) -> Result<Self, ErdError> {
+        let include = std::iter::once(&artifact.artifact_pattern)
+            .chain(&artifact.include)
+            .filter(|p| !p.is_empty())
+            .map(|p| Pattern::parse(artifact, p))
+            .collect::<Result<_, _>>()?;
+        let exclude = artifact
+            .exclude
+            .iter()
+            .map(|p| Pattern::parse(artifact, p))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            artifact: artifact.id.clone(),
+            include,
+            exclude,
+        })
+    }
+
+    /// Match a single pattern, such as another path option of the artifact
+    pub fn for_pattern(artifact: &ArtifactConfig, pattern: &str) -> Result<Self, ErdError> {
+        Ok(Self {
+            artifact: artifact.id.clone(),
+            include: vec![Pattern::parse(artifact, pattern)?],
+            exclude: vec![],
+        })
+    }
+
+    /// Whether the file at the given path is the artifact
+    pub fn matches(&self, path: &str) -> bool {
+        self.include.iter().any(|p| p.matches(path)) && !self.exclude.iter().any(|p| p.matches(path))
+    }
+
+    /// Pick the single candidate that is the artifact, given the path of each candidate.
+    /// Candidates with the same path (e.g. re-uploads) count as one, the last of which is used.
+    /// Fails with [`ErdError::AmbiguousArtifact`] if several different files match
+    pub fn select<T>(
+        &self,
+        candidates: impl IntoIterator<Item = T>,
+        path: impl Fn(&T) -> &str,
+    ) -> Result<Option<T>, ErdError> {
+        let mut found: Vec<T> = vec![];
+        for candidate in candidates {
+            if !self.matches(path(&candidate)) {
+                continue;
+            }
+            found.retain(|f| path(f) != path(&candidate));
+            found.push(candidate);
+        }
+        if found.len() > 1 {
+            return Err(ErdError::AmbiguousArtifact {
+                artifact: self.artifact.clone(),
+                candidates: found.iter().map(|f| path(f).to_string()).collect(),
+            });
+        }
+        Ok(found.pop())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matcher(pattern: &str, exclude: &[&str]) -> ArtifactMatcher {
+        ArtifactMatcher::new(&ArtifactConfig {
+            id: "plugin".into(),
+            artifact_pattern: pattern.into(),
+            exclude: exclude.iter().map(|e| e.to_string()).collect(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_patterns() {
+        let files = [
+            "build/libs/plugin-1.0.jar",
+            "build/libs/plugin-1.0-sources.jar",
+            "build/libs/plugin-1.0-javadoc.jar",
+            "README.md",
+        ];
+        let glob = matcher("*.jar", &["*-sources.jar", "*-javadoc.jar"]);
+        assert_eq!(glob.select(files, |f| f).unwrap(), Some("build/libs/plugin-1.0.jar"));
+
+        assert!(matcher("build/*/*.jar", &[]).matches("build/libs/plugin.jar"));
+        assert!(!matcher("build/*.jar", &[]).matches("build/libs/plugin.jar"));
+        assert!(matcher("/plugin-[0-9.]+\\.jar$/", &[]).matches("build/libs/plugin-1.0.jar"));
+        assert!(matcher(".jar", &[]).matches("build/libs/plugin-1.0.jar"));
+
+        match matcher("*.jar", &[]).select(files, |f| f) {
+            Err(ErdError::AmbiguousArtifact { candidates, .. }) => assert_eq!(candidates.len(), 3),
+            _ => panic!("Expected the pattern to be ambiguous"),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::time::Duration;
+
+use log::warn;
+use reqwest::blocking::Client;
+use reqwest::{Certificate, NoProxy, Proxy};
+
+use crate::config::artifacts::SourceConfig;
+use crate::ErdError;
+
+const USER_AGENT: &str = concat!("erd/", env!("CARGO_PKG_VERSION"));
+
+/// Build the HTTP client for a source from its `http` settings.
+/// Sources keep the client for all of their requests, so connections are reused
+pub fn build_client(config: &SourceConfig) -> Result<Client, ErdError> {
+    let invalid = |desc: String| ErdError::InvalidSourceConfig {
+        source: config.id.clone(),
+        desc,
+    };
+    let http = config.http.clone().unwrap_or_default();
+    let mut builder = Client::builder().user_agent(http.user_agent.as_deref().unwrap_or(USER_AGENT));
+    if let Some(seconds) = http.connect_timeout {
+        builder = builder.connect_timeout(Duration::from_secs(seconds));
+    }
+    if let Some(seconds) = http.read_timeout {
+        builder = builder.timeout(Duration::from_secs(seconds));
+    }
+    if let Some(proxy) = &http.proxy {
+        let proxy = Proxy::all(proxy).map_err(|e| invalid(format!("Invalid proxy '{}': {}", proxy, e)))?;
+        builder = builder.proxy(proxy.no_proxy(NoProxy::from_string(&http.no_proxy.join(","))));
+    }
+    if let Some(path) = &http.ca_bundle {
+        let pem = fs::read(path).map_err(|e| ErdError::IOError(e, format!("Failed to read CA bundle {:?}", path)))?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .map_err(|e| invalid(format!("Invalid CA bundle {:?}: {}", path, e)))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if http.insecure {
+        warn!("Not verifying TLS certificates for {}", config.url);
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    builder
+        .build()
+        .map_err(|e| invalid(format!("Failed to create HTTP client: {}", e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::artifacts::{HttpConfig, SourceType};
+
+    #[test]
+    fn test_build_client() {
+        let mut config = SourceConfig {
+            id: "gitlab/internal".into(),
+            kind: SourceType::Gitlab,
+            url: "https://gitlab.internal".into(),
+            region: None,
+            command: None,
+            retries: None,
+            http: Some(HttpConfig {
+                connect_timeout: Some(5),
+                proxy: Some("http://proxy.internal:3128".into()),
+                no_proxy: vec![".internal".into()],
+                insecure: true,
+                ..Default::default()
+            }),
+            artifacts: vec![],
+        };
+        build_client(&config).expect("Failed to build client");
+
+        config.http.as_mut().unwrap().ca_bundle = Some("missing-ca.pem".into());
+        assert!(matches!(build_client(&config), Err(ErdError::IOError(..))));
+    }
+}
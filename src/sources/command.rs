@@ -0,0 +1,393 @@
+//! Sources backed by an external executable, such as `erd-source-foo`.
+//!
+//! For each operation erd runs the command, writes a single JSON request to its stdin
+//! and reads a single JSON response from its stdout. Anything written to stderr is shown
+//! to the user. Requests look like:
+//!
+//! ```json
+//! {"url": "<source url>", "operation": "download", "artifact": {...}, "build_id": null, "directory": "/tmp/..."}
+//! ```
+//!
+//! | operation      | extra fields                           | response                                         |
+//! |----------------|----------------------------------------|--------------------------------------------------|
+//! | `capabilities` |                                        | `{"scan": bool, "history": bool, "rebuild": bool}` |
+//! | `scan`         | `query`                                | `{"projects": [{"id", "path", "url"}]}`          |
+//! | `history`      | `artifact`                             | `{"builds": [{"id", "status", "timestamp", ...}]}` |
+//! | `download`     | `artifact`, `build_id`, `directory`    | `{"file_name": "name in directory" or null}`     |
+//! | `rebuild`      | `artifact`, `build_id`                 | `{}`                                             |
+//!
+//! Any operation can fail by responding with `{"error": "description"}`.
+//! The source's login, if there is one, is passed in the `ERD_USERNAME` and `ERD_PASSWORD`
+//! environment variables.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::config::artifacts::{ArtifactConfig, SourceConfig, SourceType};
+use crate::download::Download;
+use crate::logins::Login;
+use crate::output::ScannedProject;
+use crate::{ArtifactFiles, ErdError, FileData};
+
+use super::{ArtifactSource, BuildInfo, Capabilities};
+
+#[derive(Serialize)]
+#[serde(tag = "operation", rename_all = "lowercase")]
+enum Request<'a> {
+    Capabilities,
+    Scan {
+        query: Option<String>,
+    },
+    History {
+        artifact: &'a ArtifactConfig,
+    },
+    Download {
+        artifact: &'a ArtifactConfig,
+        build_id: Option<String>,
+        directory: &'a Path,
+    },
+    Rebuild {
+        artifact: &'a ArtifactConfig,
+        build_id: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    url: &'a str,
+    #[serde(flatten)]
+    request: Request<'a>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Response<T> {
+    Error { error: String },
+    Ok(T),
+}
+
+#[derive(Deserialize)]
+pub struct CapabilitiesResponse {
+    #[serde(default)]
+    pub scan: bool,
+    #[serde(default)]
+    pub history: bool,
+    #[serde(default)]
+    pub rebuild: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ScanResponse {
+    pub projects: Vec<PluginProject>,
+}
+
+#[derive(Deserialize)]
+pub struct PluginProject {
+    pub id: String,
+    #[serde(default)]
+    pub path: String,
+    #[serde(default)]
+    pub url: String,
+}
+
+#[derive(Deserialize)]
+pub struct HistoryResponse {
+    pub builds: Vec<PluginBuild>,
+}
+
+#[derive(Deserialize)]
+pub struct PluginBuild {
+    pub id: String,
+    #[serde(default)]
+    pub job_ref: String,
+    #[serde(default)]
+    pub timestamp: String,
+    #[serde(default = "default_status")]
+    pub status: String,
+    #[serde(default = "default_has_artifacts")]
+    pub has_artifacts: bool,
+    #[serde(default)]
+    pub web_url: String,
+    #[serde(default)]
+    pub commit_short_id: String,
+    #[serde(default)]
+    pub commit_title: String,
+    #[serde(default)]
+    pub commit_author: String,
+    #[serde(default)]
+    pub notes: String,
+}
+
+fn default_status() -> String {
+    "available".to_string()
+}
+
+fn default_has_artifacts() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+pub struct DownloadResponse {
+    pub file_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RebuildResponse {}
+
+/// Artifacts provided by an external executable, for build systems erd does not know about
+pub struct CommandSource {
+    url: String,
+    command: Vec<String>,
+    login: Option<Login>,
+    capabilities: Capabilities,
+}
+
+impl CommandSource {
+    /// Create the source, asking the command which operations it supports
+    pub fn new(config: &SourceConfig, login: Option<Login>) -> Result<Self, ErdError> {
+        let command = config
+            .command
+            .clone()
+            .filter(|c| !c.is_empty())
+            .ok_or_else(|| ErdError::CommandFailed {
+                command: config.id.clone(),
+                desc: "command must be set for command sources".to_string(),
+            })?;
+        let mut source = Self {
+            url: config.url.clone(),
+            command,
+            login,
+            capabilities: Capabilities {
+                scan: false,
+                history: false,
+                rebuild: false,
+            },
+        };
+        let capabilities: CapabilitiesResponse = source.run(Request::Capabilities)?;
+        source.capabilities = Capabilities {
+            scan: capabilities.scan,
+            history: capabilities.history,
+            rebuild: capabilities.rebuild,
+        };
+        Ok(source)
+    }
+
+    fn command_failed(&self, desc: String) -> ErdError {
+        ErdError::CommandFailed {
+            command: self.command.join(" "),
+            desc,
+        }
+    }
+
+    /// Run the command with the given request, returning its response
+    fn run<T: DeserializeOwned>(&self, request: Request) -> Result<T, ErdError> {
+        let message = serde_json::to_vec(&Message {
+            url: &self.url,
+            request,
+        })
+        .expect("Requests should always serialize");
+        debug!("Running {:?}", self.command);
+
+        let mut command = Command::new(&self.command[0]);
+        command
+            .args(&self.command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        if let Some(login) = &self.login {
+            command
+                .env("ERD_USERNAME", &login.username)
+                .env("ERD_PASSWORD", &login.password);
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|e| ErdError::IOError(e, format!("Failed to run {}", self.command[0])))?;
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(&message)
+            .map_err(|e| ErdError::IOError(e, format!("Failed to send request to {}", self.command[0])))?;
+        let output = child
+            .wait_with_output()
+            .map_err(|e| ErdError::IOError(e, format!("Failed to wait for {}", self.command[0])))?;
+        if !output.status.success() {
+            return Err(self.command_failed(format!("Exited with {}", output.status)));
+        }
+
+        let response: Response<T> = serde_json::from_slice(&output.stdout)
+            .map_err(|e| self.command_failed(format!("Invalid response: {}", e)))?;
+        match response {
+            Response::Ok(response) => Ok(response),
+            Response::Error { error } => Err(self.command_failed(error)),
+        }
+    }
+}
+
+impl ArtifactSource for CommandSource {
+    fn kind(&self) -> SourceType {
+        SourceType::Command
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn scan(&self, query: Option<String>) -> Result<Vec<ScannedProject>, ErdError> {
+        let response: ScanResponse = self.run(Request::Scan { query })?;
+        Ok(response
+            .projects
+            .into_iter()
+            .map(|p| ScannedProject {
+                path: match p.path.is_empty() {
+                    true => p.id.clone(),
+                    false => p.path,
+                },
+                id: p.id,
+                url: p.url,
+            })
+            .collect())
+    }
+
+    fn history(&self, artifact: &ArtifactConfig) -> Result<Vec<BuildInfo>, ErdError> {
+        let response: HistoryResponse = self.run(Request::History { artifact })?;
+        Ok(response.builds.into_iter().map(BuildInfo::from).collect())
+    }
+
+    fn download(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<String>,
+    ) -> Result<Option<ArtifactFiles>, ErdError> {
+        let directory = tempfile::tempdir()
+            .map_err(|e| ErdError::IOError(e, "Failed to create download directory".to_string()))?;
+        let response: DownloadResponse = self.run(Request::Download {
+            artifact,
+            build_id,
+            directory: directory.path(),
+        })?;
+        // Only take the file name, so that the command cannot point us outside the directory
+        let file_name = match response.file_name.as_deref().map(Path::new).and_then(Path::file_name) {
+            Some(file_name) => PathBuf::from(file_name),
+            None => return Ok(None),
+        };
+        let path = directory.path().join(&file_name);
+        let file = File::open(&path)
+            .map_err(|e| ErdError::IOError(e, format!("Failed to open {:?} written by the command", path)))?;
+        let data = Download::from_reader(file, &format!("Failed to read {:?} written by the command", path))?;
+        Ok(Some(FileData { file_name, data, mode: None }.into()))
+    }
+
+    fn rebuild(&self, artifact: &ArtifactConfig, build_id: String) -> Result<(), ErdError> {
+        let _: RebuildResponse = self.run(Request::Rebuild { artifact, build_id })?;
+        Ok(())
+    }
+}
+
+impl From<PluginBuild> for BuildInfo {
+    fn from(build: PluginBuild) -> Self {
+        BuildInfo {
+            id: build.id,
+            job_ref: build.job_ref,
+            timestamp: build.timestamp,
+            status: build.status,
+            has_artifacts: build.has_artifacts,
+            web_url: build.web_url,
+            commit_short_id: build.commit_short_id,
+            commit_title: build.commit_title,
+            commit_author: build.commit_author,
+            notes: build.notes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::sources::test_server::{assert_downloads, test_artifact};
+
+    const PLUGIN: &str = r#"#!/bin/sh
+request=$(cat)
+directory=$(echo "$request" | sed 's/.*"directory":"\([^"]*\)".*/\1/')
+case "$request" in
+    *'"operation":"capabilities"'*)
+        echo '{"scan": false, "history": true, "rebuild": true}' ;;
+    *'"operation":"history"'*)
+        echo '{"builds": [{"id": "2", "timestamp": "2024-01-02"}, {"id": "1"}]}' ;;
+    *'"operation":"download"'*'"build_id":"7"'*)
+        printf 'build 7' > "$directory/plugin.jar"
+        echo '{"file_name": "../../plugin.jar"}' ;;
+    *'"operation":"download"'*'"build_id":"8"'*)
+        echo '{"file_name": null}' ;;
+    *'"operation":"download"'*)
+        printf 'build %s' "$ERD_USERNAME" > "$directory/plugin.jar"
+        echo '{"file_name": "plugin.jar"}' ;;
+    *'"operation":"rebuild"'*)
+        echo '{"error": "build 2 is still running"}' ;;
+    *)
+        exit 3 ;;
+esac
+"#;
+
+    fn plugin_source(dir: &Path) -> CommandSource {
+        let plugin = dir.join("erd-source-test");
+        fs::write(&plugin, PLUGIN).unwrap();
+        let config = SourceConfig {
+            id: "internal".into(),
+            kind: SourceType::Command,
+            url: "https://builds.internal".into(),
+            region: None,
+            command: Some(vec!["sh".into(), plugin.to_string_lossy().to_string()]),
+            retries: None,
+            http: None,
+            artifacts: vec![],
+        };
+        let login = Login {
+            url: "https://builds.internal".into(),
+            username: "ci".into(),
+            password: "secret".into(),
+        };
+        CommandSource::new(&config, Some(login)).expect("Failed to create source")
+    }
+
+    #[test]
+    fn test_shell_plugin() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = plugin_source(dir.path());
+        assert!(source.capabilities().history);
+        assert!(!source.capabilities().scan);
+
+        let artifact = test_artifact(".jar");
+        let history = source.history(&artifact).unwrap();
+        let ids: Vec<&str> = history.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, ["2", "1"]);
+
+        // The login is passed in the environment
+        assert_downloads(&source, &artifact, None, "plugin.jar", b"build ci");
+        // Paths outside the download directory are cut down to their file name
+        assert_downloads(&source, &artifact, Some("7"), "plugin.jar", b"build 7");
+        assert!(source.download(&artifact, Some("8".into())).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_plugin_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = plugin_source(dir.path());
+        match source.rebuild(&test_artifact(".jar"), "2".into()) {
+            Err(ErdError::CommandFailed { desc, .. }) => assert_eq!(desc, "build 2 is still running"),
+            _ => panic!("Expected the plugin's error"),
+        }
+        match source.scan(None) {
+            Err(ErdError::CommandFailed { desc, .. }) => assert!(desc.contains("exit status: 3"), "{}", desc),
+            _ => panic!("Expected the plugin to exit with an error"),
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use log::{debug, warn};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{HeaderValue, ACCEPT, AUTHORIZATION};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::archive::find_artifact_in_archive;
+use crate::config::artifacts::{ArtifactConfig, SourceType};
+use crate::download::{Download, Resumable};
+use crate::output::ScannedProject;
+use crate::{ArtifactFiles, ErdError};
+
+use super::{ArtifactSource, BuildInfo, Capabilities};
+use super::retry::{Retry, SendRetrying};
+
+/// The path of the REST API, relative to the root URL of a Gitea or Forgejo instance
+const API_PATH: &str = "api/v1";
+
+#[derive(Deserialize)]
+pub struct RepositoryData {
+    pub full_name: String,
+    pub html_url: String,
+}
+
+#[derive(Deserialize)]
+pub struct RepositorySearch {
+    pub data: Vec<RepositoryData>,
+}
+
+#[derive(Deserialize)]
+pub struct WorkflowRuns {
+    pub workflow_runs: Vec<WorkflowRun>,
+}
+
+#[derive(Deserialize)]
+pub struct WorkflowRun {
+    pub id: usize,
+    #[serde(default)]
+    pub display_title: String,
+    #[serde(default)]
+    pub head_branch: String,
+    #[serde(default)]
+    pub head_sha: String,
+    pub status: Option<String>,
+    pub conclusion: Option<String>,
+    #[serde(default)]
+    pub started_at: String,
+    #[serde(default)]
+    pub html_url: String,
+    pub actor: Option<User>,
+}
+
+#[derive(Deserialize)]
+pub struct User {
+    pub login: String,
+}
+
+#[derive(Deserialize)]
+pub struct WorkflowArtifacts {
+    pub artifacts: Vec<WorkflowArtifact>,
+}
+
+#[derive(Deserialize)]
+pub struct WorkflowArtifact {
+    pub id: usize,
+    pub name: String,
+    #[serde(default)]
+    pub expired: bool,
+}
+
+/// Get the base URL of the REST API for the Gitea or Forgejo instance at the given URL.
+pub fn get_api_url(source_url: &str) -> String {
+    format!("{}/{}", source_url.trim_end_matches('/'), API_PATH)
+}
+
+fn get_token_value(token: &str) -> Result<HeaderValue, ErdError> {
+    format!("token {}", token)
+        .parse()
+        .map_err(|_| ErdError::InvalidToken(token.to_string()))
+}
+
+/// Workflow run artifacts from Gitea or Forgejo Actions
+pub struct GiteaSource {
+    url: String,
+    token: Option<String>,
+    client: Client,
+    retry: Retry,
+}
+
+impl GiteaSource {
+    pub fn new(url: String, token: Option<String>, client: Client, retry: Retry) -> Self {
+        Self { url, token, client, retry }
+    }
+
+    fn check_status(&self, response: Response, what: &str) -> Result<Response, ErdError> {
+        super::check_status(SourceType::Gitea, &self.url, response, what)
+    }
+
+    fn get(&self, client: &Client, path: &str) -> Result<RequestBuilder, ErdError> {
+        let url = format!("{}/{}", get_api_url(&self.url), path);
+        let mut request = client.get(url).header(ACCEPT, "application/json");
+        if let Some(token) = &self.token {
+            request = request.header(AUTHORIZATION, get_token_value(token)?);
+        }
+        Ok(request)
+    }
+
+    fn get_runs(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+        status: Option<&str>,
+        limit: &str,
+    ) -> Result<Vec<WorkflowRun>, ErdError> {
+        // Gitea only has a listing of workflow runs from 1.24 onwards
+        let path = format!("repos/{}/actions/runs", artifact.project_id);
+        let mut query = vec![("branch", artifact.branch.as_str()), ("limit", limit)];
+        if let Some(status) = status {
+            query.push(("status", status));
+        }
+        let response = self
+            .get(client, &path)?
+            .query(&query)
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get workflow runs"))
+            .and_then(|r| self.check_status(r, "Received Error while getting workflow runs"))?;
+        debug!("URL: {}", response.url());
+        let runs: WorkflowRuns = deserialize_response(response)?;
+        Ok(runs.workflow_runs)
+    }
+
+    fn get_run_artifacts(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+        run_id: &str,
+    ) -> Result<Vec<WorkflowArtifact>, ErdError> {
+        let path = format!("repos/{}/actions/runs/{}/artifacts", artifact.project_id, run_id);
+        let response = self
+            .get(client, &path)?
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to list artifacts"))
+            .and_then(|r| self.check_status(r, "Received Error while listing artifacts"))?;
+        let run_artifacts: WorkflowArtifacts = deserialize_response(response)?;
+        Ok(run_artifacts.artifacts)
+    }
+
+    fn download_run_artifact(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+        run_artifact: &WorkflowArtifact,
+    ) -> Result<Download, ErdError> {
+        debug!("Downloading artifact {} ({})", run_artifact.name, run_artifact.id);
+        let path = format!("repos/{}/actions/artifacts/{}/zip", artifact.project_id, run_artifact.id);
+        let (request, resumable) = Resumable::prepare(self.get(client, &path)?);
+        let response = request
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get artifact"))
+            .and_then(|r| self.check_status(r, "Received Error while downloading artifact"))?;
+        let download = resumable.finish(response, "Failed to read data from artifact zip")?;
+        debug!("{} bytes read", download.len());
+        Ok(download)
+    }
+}
+
+impl ArtifactSource for GiteaSource {
+    fn kind(&self) -> SourceType {
+        SourceType::Gitea
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            scan: true,
+            history: true,
+            // Gitea and Forgejo have no API for re-running workflows
+            rebuild: false,
+        }
+    }
+
+    fn scan(&self, query: Option<String>) -> Result<Vec<ScannedProject>, ErdError> {
+        let client = &self.client;
+        if self.token.is_none() {
+            warn!("Scanning without login - only public repositories can be found.");
+        }
+        let response = self
+            .get(client, "repos/search")?
+            .query(&[
+                ("q", query.as_deref().unwrap_or("")),
+                ("sort", "updated"),
+                ("order", "desc"),
+                ("limit", "30"),
+            ])
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get repository list"))
+            .and_then(|r| self.check_status(r, "Received Error while getting repository list"))?;
+        debug!("Got HTTP Code {}", response.status());
+        let search: RepositorySearch = deserialize_response(response)?;
+        Ok(search.data.into_iter().map(ScannedProject::from).collect())
+    }
+
+    fn latest_build_id(&self, artifact: &ArtifactConfig) -> Result<Option<String>, ErdError> {
+        let runs = self.get_runs(&self.client, artifact, Some("success"), "1")?;
+        Ok(runs.first().map(|r| r.id.to_string()))
+    }
+
+    fn download(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<String>,
+    ) -> Result<Option<ArtifactFiles>, ErdError> {
+        let client = &self.client;
+        let run_id = match build_id {
+            Some(b_id) => b_id,
+            None => match self.get_runs(client, artifact, Some("success"), "1")?.first() {
+                Some(run) => run.id.to_string(),
+                None => {
+                    warn!(
+                        "No successful workflow runs for {} on branch {}",
+                        artifact.project_id, artifact.branch
+                    );
+                    return Ok(None);
+                }
+            },
+        };
+        debug!("Using workflow run {}", run_id);
+
+        for run_artifact in self.get_run_artifacts(client, artifact, &run_id)? {
+            if run_artifact.expired {
+                debug!("Skipping expired artifact {}", run_artifact.name);
+                continue;
+            }
+            let download = self.download_run_artifact(client, artifact, &run_artifact)?;
+            if let Some(file_data) = find_artifact_in_archive(download, artifact)? {
+                return Ok(Some(file_data));
+            }
+        }
+        Ok(None)
+    }
+
+    fn history(&self, artifact: &ArtifactConfig) -> Result<Vec<BuildInfo>, ErdError> {
+        let client = &self.client;
+        let mut builds = vec![];
+        for run in self.get_runs(client, artifact, None, "6")? {
+            let run_artifacts = self.get_run_artifacts(client, artifact, &run.id.to_string())?;
+            let has_artifacts = run_artifacts.iter().any(|a| !a.expired);
+            builds.push(run.into_build_info(has_artifacts));
+        }
+        Ok(builds)
+    }
+}
+
+fn deserialize_response<T: DeserializeOwned>(response: Response) -> Result<T, ErdError> {
+    super::deserialize_response(SourceType::Gitea, response)
+}
+
+fn request_failed(error: reqwest::Error, what: &str) -> ErdError {
+    super::request_failed(SourceType::Gitea, error, what)
+}
+
+impl From<RepositoryData> for ScannedProject {
+    fn from(repository: RepositoryData) -> Self {
+        ScannedProject {
+            path: repository.full_name.clone(),
+            id: repository.full_name,
+            url: repository.html_url,
+        }
+    }
+}
+
+impl WorkflowRun {
+    fn into_build_info(self, has_artifacts: bool) -> BuildInfo {
+        let status = self
+            .conclusion
+            .filter(|c| !c.is_empty())
+            .or(self.status)
+            .unwrap_or_else(|| "unknown".to_string());
+        BuildInfo {
+            id: self.id.to_string(),
+            job_ref: self.head_branch,
+            timestamp: self.started_at,
+            status,
+            has_artifacts,
+            web_url: self.html_url,
+            commit_short_id: self.head_sha.chars().take(8).collect(),
+            commit_title: self.display_title,
+            commit_author: self.actor.map(|a| a.login).unwrap_or_default(),
+            notes: String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sources::test_server::{assert_downloads, serve, test_artifact, zip_of, Route};
+
+    const AUTHORIZATION_HEADER: &str = "Authorization: token secret";
+
+    #[test]
+    fn test_download_latest() {
+        let runs = r#"{"workflow_runs": [{"id": 7, "head_branch": "main", "status": "completed", "conclusion": "success"}], "total_count": 1}"#;
+        let artifacts = r#"{"artifacts": [
+            {"id": 2, "name": "old-jars", "expired": true},
+            {"id": 3, "name": "jars", "expired": false}
+        ], "total_count": 2}"#;
+        let zip = zip_of(&[("build/libs/plugin-1.0.jar", b"jar contents"), ("README.md", b"readme")]);
+        // Every request carries the token, and the expired artifact is never downloaded
+        let url = serve(vec![
+            Route::new("/api/v1/repos/owner/plugin/actions/runs", runs).requiring_header(AUTHORIZATION_HEADER),
+            Route::new("/api/v1/repos/owner/plugin/actions/runs/7/artifacts", artifacts)
+                .requiring_header(AUTHORIZATION_HEADER),
+            Route::new("/api/v1/repos/owner/plugin/actions/artifacts/3/zip", zip).requiring_header(AUTHORIZATION_HEADER),
+        ]);
+
+        let source = GiteaSource::new(url, Some("secret".into()), Client::new(), Retry::default());
+        assert_downloads(&source, &test_artifact(".jar"), None, "plugin-1.0.jar", b"jar contents");
+        assert_eq!(source.latest_build_id(&test_artifact(".jar")).unwrap().as_deref(), Some("7"));
+    }
+
+    #[test]
+    fn test_download_errors() {
+        let url = serve(vec![
+            Route::new("/api/v1/repos/owner/plugin/actions/runs", r#"{"workflow_runs": []}"#)
+                .requiring_header(AUTHORIZATION_HEADER),
+        ]);
+        let artifact = test_artifact(".jar");
+
+        let anonymous = GiteaSource::new(url.clone(), None, Client::new(), Retry::default());
+        assert!(matches!(anonymous.download(&artifact, None), Err(ErdError::Unauthorized { .. })));
+
+        let source = GiteaSource::new(url, Some("secret".into()), Client::new(), Retry::default());
+        assert!(source.download(&artifact, None).unwrap().is_none());
+        assert!(matches!(
+            source.download(&artifact, Some("8".into())),
+            Err(ErdError::NotFound { .. })
+        ));
+    }
+}
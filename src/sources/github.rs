@@ -0,0 +1,469 @@
+use log::{debug, info, warn};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{HeaderValue, ACCEPT, AUTHORIZATION};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::archive::find_artifact_in_archive;
+use crate::config::artifacts::{ArtifactConfig, SourceType};
+use crate::download::{Download, Resumable};
+use crate::output::ScannedProject;
+use crate::{ArtifactFiles, ErdError};
+
+use super::{ArtifactSource, BuildInfo, Capabilities};
+use super::retry::{Retry, SendRetrying};
+
+/// The REST API of github.com, which is served from its own host
+const PUBLIC_API_URL: &str = "https://api.github.com";
+/// The path of the REST API, relative to the root URL of a GitHub Enterprise Server
+const API_PATH: &str = "api/v3";
+const API_VERSION_HEADER: &str = "x-github-api-version";
+const API_VERSION: &str = "2022-11-28";
+
+#[derive(Deserialize)]
+pub struct RepositoryData {
+    pub full_name: String,
+    pub html_url: String,
+}
+
+#[derive(Deserialize)]
+pub struct RepositorySearch {
+    pub items: Vec<RepositoryData>,
+}
+
+#[derive(Deserialize)]
+pub struct WorkflowRuns {
+    pub workflow_runs: Vec<WorkflowRun>,
+}
+
+#[derive(Deserialize)]
+pub struct WorkflowRun {
+    pub id: usize,
+    pub head_branch: Option<String>,
+    pub head_sha: String,
+    pub status: Option<String>,
+    pub conclusion: Option<String>,
+    pub created_at: String,
+    pub html_url: String,
+    pub head_commit: Option<HeadCommit>,
+}
+
+impl WorkflowRun {
+    /// The conclusion of the run if it has finished, otherwise its current status
+    fn get_status(&self) -> String {
+        self.conclusion
+            .clone()
+            .or_else(|| self.status.clone())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct HeadCommit {
+    pub message: String,
+    pub author: Option<CommitAuthor>,
+}
+
+#[derive(Deserialize)]
+pub struct CommitAuthor {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct WorkflowArtifacts {
+    pub artifacts: Vec<WorkflowArtifact>,
+}
+
+#[derive(Deserialize)]
+pub struct WorkflowArtifact {
+    pub id: usize,
+    pub name: String,
+    pub expired: bool,
+    pub archive_download_url: String,
+}
+
+fn get_token_value(token: &str) -> Result<HeaderValue, ErdError> {
+    format!("Bearer {}", token)
+        .parse()
+        .map_err(|_| ErdError::InvalidToken(token.to_string()))
+}
+
+/// Get the base URL of the REST API for the GitHub instance at the given URL.
+/// github.com has a separate API host, while GitHub Enterprise Server serves it under `/api/v3`
+pub fn get_api_url(source_url: &str) -> String {
+    let host = reqwest::Url::parse(source_url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_lowercase));
+    match host.as_deref() {
+        Some("github.com") | Some("www.github.com") | Some("api.github.com") | None => PUBLIC_API_URL.to_string(),
+        Some(_) => format!("{}/{}", source_url.trim_end_matches('/'), API_PATH),
+    }
+}
+
+/// Add the headers that every GitHub API request should carry
+fn with_headers(request: RequestBuilder, token: Option<&str>) -> Result<RequestBuilder, ErdError> {
+    let mut request = request
+        .header(ACCEPT, "application/vnd.github+json")
+        .header(API_VERSION_HEADER, API_VERSION);
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, get_token_value(token)?);
+    }
+    Ok(request)
+}
+
+/// Workflow run artifacts from GitHub Actions
+pub struct GithubSource {
+    url: String,
+    token: Option<String>,
+    client: Client,
+    retry: Retry,
+}
+
+impl GithubSource {
+    pub fn new(url: String, token: Option<String>, client: Client, retry: Retry) -> Self {
+        Self { url, token, client, retry }
+    }
+
+    fn check_status(&self, response: Response, what: &str) -> Result<Response, ErdError> {
+        super::check_status(SourceType::Github, &self.url, response, what)
+    }
+
+    fn token(&self) -> Result<&str, ErdError> {
+        super::require_token(&self.token, &self.url)
+    }
+
+    fn get_latest_run(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+    ) -> Result<Option<WorkflowRun>, ErdError> {
+        let url = format!("{}/repos/{}/actions/runs", get_api_url(&self.url), artifact.project_id);
+        let request = client.get(url).query(&[
+            ("branch", artifact.branch.as_str()),
+            ("status", "success"),
+            ("exclude_pull_requests", "true"),
+            ("per_page", "1"),
+        ]);
+        let response = with_headers(request, Some(self.token()?))?
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get workflow runs from GitHub"))
+            .and_then(|r| self.check_status(r, "Received Error while getting workflow runs"))?;
+        debug!("URL: {}", response.url());
+        let runs: WorkflowRuns = deserialize_response(response)?;
+        Ok(runs.workflow_runs.into_iter().next())
+    }
+
+    fn get_run_artifacts(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+        run_id: &str,
+    ) -> Result<WorkflowArtifacts, ErdError> {
+        let url = format!(
+            "{}/repos/{}/actions/runs/{}/artifacts",
+            get_api_url(&self.url), artifact.project_id, run_id
+        );
+        let request = client.get(url);
+        let response = with_headers(request, Some(self.token()?))?
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to list artifacts from GitHub"))
+            .and_then(|r| self.check_status(r, "Received Error while listing artifacts"))?;
+        deserialize_response(response)
+    }
+
+    fn download_run_artifact(
+        &self,
+        client: &Client,
+        run_artifact: &WorkflowArtifact,
+    ) -> Result<Download, ErdError> {
+        debug!("Downloading artifact {} ({})", run_artifact.name, run_artifact.id);
+        let request = client.get(&run_artifact.archive_download_url);
+        let (request, resumable) = Resumable::prepare(with_headers(request, Some(self.token()?))?);
+        let response = request
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get artifact from GitHub"))
+            .and_then(|r| self.check_status(r, "Received Error while downloading artifact"))?;
+        let download = resumable.finish(response, "Failed to read data from artifact zip")?;
+        debug!("{} bytes read", download.len());
+        Ok(download)
+    }
+}
+
+impl ArtifactSource for GithubSource {
+    fn kind(&self) -> SourceType {
+        SourceType::Github
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            scan: true,
+            history: true,
+            rebuild: true,
+        }
+    }
+
+    fn scan(&self, query: Option<String>) -> Result<Vec<ScannedProject>, ErdError> {
+        let client = &self.client;
+        let token = self.token.as_deref();
+        let repositories = match token {
+            Some(_) => {
+                // https://docs.github.com/en/rest/repos/repos#list-repositories-for-the-authenticated-user
+                let url = format!("{}/user/repos", get_api_url(&self.url));
+                let request = client
+                    .get(url)
+                    .query(&[("sort", "pushed"), ("per_page", "100")]);
+                let response = with_headers(request, token)?
+                    .send_retrying(&self.retry)
+                    .map_err(|e| request_failed(e, "Failed to get repository list"))
+                    .and_then(|r| self.check_status(r, "Received Error while getting repository list"))?;
+                debug!("Got HTTP Code {}", response.status());
+                let repositories: Vec<RepositoryData> = deserialize_response(response)?;
+                match &query {
+                    Some(q) => {
+                        let q = q.to_lowercase();
+                        repositories
+                            .into_iter()
+                            .filter(|r| r.full_name.to_lowercase().contains(&q))
+                            .take(30)
+                            .collect()
+                    }
+                    None => repositories.into_iter().take(30).collect(),
+                }
+            }
+            None => {
+                warn!("Scanning without login - only public repositories can be found.");
+                // https://docs.github.com/en/rest/search/search#search-repositories
+                let url = format!("{}/search/repositories", get_api_url(&self.url));
+                let request = client.get(url).query(&[
+                    ("q", query.as_deref().unwrap_or("")),
+                    ("sort", "updated"),
+                    ("per_page", "30"),
+                ]);
+                let response = with_headers(request, token)?
+                    .send_retrying(&self.retry)
+                    .map_err(|e| request_failed(e, "Failed to search repositories"))
+                    .and_then(|r| self.check_status(r, "Received Error while searching repositories"))?;
+                debug!("Got HTTP Code {}", response.status());
+                let search: RepositorySearch = deserialize_response(response)?;
+                search.items
+            }
+        };
+        Ok(repositories.into_iter().map(ScannedProject::from).collect())
+    }
+
+    fn latest_build_id(&self, artifact: &ArtifactConfig) -> Result<Option<String>, ErdError> {
+        let run = self.get_latest_run(&self.client, artifact)?;
+        Ok(run.map(|r| r.id.to_string()))
+    }
+
+    fn download(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<String>,
+    ) -> Result<Option<ArtifactFiles>, ErdError> {
+        let client = &self.client;
+        let run_id = match build_id {
+            Some(b_id) => b_id,
+            None => match self.get_latest_run(client, artifact)? {
+                Some(run) => run.id.to_string(),
+                None => {
+                    warn!(
+                        "No successful workflow runs for {} on branch {}",
+                        artifact.project_id, artifact.branch
+                    );
+                    return Ok(None);
+                }
+            },
+        };
+        debug!("Using workflow run {}", run_id);
+
+        let run_artifacts = self.get_run_artifacts(client, artifact, &run_id)?;
+        let mut any_expired = false;
+        for run_artifact in run_artifacts.artifacts {
+            if run_artifact.expired {
+                debug!("Skipping expired artifact {}", run_artifact.name);
+                any_expired = true;
+                continue;
+            }
+            let download = self.download_run_artifact(client, &run_artifact)?;
+            if let Some(file_data) = find_artifact_in_archive(download, artifact)? {
+                return Ok(Some(file_data));
+            }
+        }
+        // The file may well have been in one of the expired artifacts
+        if any_expired {
+            return Err(ErdError::ArtifactExpired {
+                artifact: artifact.id.clone(),
+                build_id: run_id,
+            });
+        }
+        Ok(None)
+    }
+
+    fn history(&self, artifact: &ArtifactConfig) -> Result<Vec<BuildInfo>, ErdError> {
+        let client = &self.client;
+        let url = format!("{}/repos/{}/actions/runs", get_api_url(&self.url), artifact.project_id);
+        let request = client.get(url).query(&[
+            ("branch", artifact.branch.as_str()),
+            ("exclude_pull_requests", "true"),
+            ("per_page", "6"),
+        ]);
+        let response = with_headers(request, Some(self.token()?))?
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get workflow runs from GitHub"))
+            .and_then(|r| self.check_status(r, "Received Error while getting workflow runs"))?;
+        debug!("URL: {}", response.url());
+        let runs: WorkflowRuns = deserialize_response(response)?;
+
+        let mut builds = vec![];
+        for run in runs.workflow_runs {
+            let run_artifacts = self.get_run_artifacts(client, artifact, &run.id.to_string())?;
+            let has_artifacts = run_artifacts.artifacts.iter().any(|a| !a.expired);
+            builds.push(run.into_build_info(has_artifacts));
+        }
+        Ok(builds)
+    }
+
+    fn rebuild(&self, artifact: &ArtifactConfig, build_id: String) -> Result<(), ErdError> {
+        let client = &self.client;
+        // https://docs.github.com/en/rest/actions/workflow-runs#re-run-a-workflow
+        let rerun_url = format!(
+            "{}/repos/{}/actions/runs/{}/rerun",
+            get_api_url(&self.url), artifact.project_id, build_id
+        );
+        let request = client.post(&rerun_url);
+        with_headers(request, Some(self.token()?))?
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, &format!("Failed to re-run workflow run {} on GitHub", build_id)))
+            .and_then(|r| self.check_status(r, &format!("GitHub refused to re-run workflow run {}", build_id)))?;
+        info!("Requested a re-run of workflow run {}", build_id);
+
+        let run_url = format!(
+            "{}/repos/{}/actions/runs/{}",
+            get_api_url(&self.url), artifact.project_id, build_id
+        );
+        let request = client.get(&run_url);
+        let response = with_headers(request, Some(self.token()?))?
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get re-run workflow run"))
+            .and_then(|r| self.check_status(r, "Received Error while getting re-run workflow run"))?;
+        let run: WorkflowRun = deserialize_response(response)?;
+        info!("> {} - {}", run.get_status(), run.html_url);
+        info!("Check the job history to see when the workflow run is complete");
+        Ok(())
+    }
+}
+
+fn deserialize_response<T: DeserializeOwned>(response: Response) -> Result<T, ErdError> {
+    super::deserialize_response(SourceType::Github, response)
+}
+
+fn request_failed(error: reqwest::Error, what: &str) -> ErdError {
+    super::request_failed(SourceType::Github, error, what)
+}
+
+impl From<RepositoryData> for ScannedProject {
+    fn from(repository: RepositoryData) -> Self {
+        ScannedProject {
+            path: repository.full_name.clone(),
+            // GitHub addresses repositories by their full name rather than numeric id
+            id: repository.full_name,
+            url: repository.html_url,
+        }
+    }
+}
+
+impl WorkflowRun {
+    fn into_build_info(self, has_artifacts: bool) -> BuildInfo {
+        let status = self.get_status();
+        let (commit_title, commit_author) = match self.head_commit {
+            Some(commit) => (
+                commit.message.lines().next().unwrap_or_default().to_string(),
+                commit.author.map(|a| a.email).unwrap_or_default(),
+            ),
+            None => (String::new(), String::new()),
+        };
+        BuildInfo {
+            id: self.id.to_string(),
+            job_ref: self.head_branch.unwrap_or_default(),
+            timestamp: self.created_at,
+            status,
+            has_artifacts,
+            web_url: self.html_url,
+            commit_short_id: self.head_sha.chars().take(8).collect(),
+            commit_title,
+            commit_author,
+            notes: String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sources::test_server::{assert_downloads, serve, test_artifact, zip_of, Route};
+
+    const AUTHORIZATION_HEADER: &str = "Authorization: Bearer token";
+
+    #[test]
+    fn test_api_url() {
+        assert_eq!(get_api_url("https://github.com/"), "https://api.github.com");
+        assert_eq!(get_api_url("https://github.example.com/"), "https://github.example.com/api/v3");
+    }
+
+    #[test]
+    fn test_download_latest() {
+        let zip = zip_of(&[("build/libs/plugin-1.0.jar", b"jar contents"), ("README.md", b"readme")]);
+        // Artifacts are downloaded from wherever the API points, usually another host
+        let storage_url = serve(vec![Route::new("/artifacts/3/zip", zip)]);
+        let runs = r#"{"workflow_runs": [{"id": 7, "head_branch": "main", "head_sha": "abc", "created_at": "", "html_url": ""}]}"#;
+        let artifacts = format!(
+            r#"{{"artifacts": [{{"id": 3, "name": "jars", "expired": false, "archive_download_url": "{}/artifacts/3/zip"}}]}}"#,
+            storage_url
+        );
+        // A GitHub Enterprise Server, which serves the API under /api/v3
+        let url = serve(vec![
+            Route::new("/api/v3/repos/owner/plugin/actions/runs", runs).requiring_header(AUTHORIZATION_HEADER),
+            Route::new("/api/v3/repos/owner/plugin/actions/runs/7/artifacts", artifacts)
+                .requiring_header(AUTHORIZATION_HEADER),
+        ]);
+
+        let source = GithubSource::new(url, Some("token".into()), Client::new(), Retry::default());
+        let artifact = test_artifact(".jar");
+        assert_downloads(&source, &artifact, None, "plugin-1.0.jar", b"jar contents");
+        assert_eq!(source.latest_build_id(&artifact).unwrap().as_deref(), Some("7"));
+    }
+
+    #[test]
+    fn test_download_errors() {
+        let url = serve(vec![
+            Route::new("/api/v3/repos/owner/private/actions/runs", "{}").with_status("401 Unauthorized"),
+            Route::new("/api/v3/repos/owner/plugin/actions/runs", r#"{"workflow_runs": []}"#),
+            Route::new(
+                "/api/v3/repos/owner/plugin/actions/runs/8/artifacts",
+                r#"{"artifacts": [{"id": 3, "name": "jars", "expired": true, "archive_download_url": ""}]}"#,
+            ),
+        ]);
+        let source = GithubSource::new(url, Some("token".into()), Client::new(), Retry::default());
+        let artifact = |project_id: &str| ArtifactConfig {
+            project_id: project_id.into(),
+            ..test_artifact(".jar")
+        };
+        assert!(matches!(
+            source.download(&artifact("owner/private"), None),
+            Err(ErdError::Unauthorized { .. })
+        ));
+        assert!(matches!(
+            source.download(&artifact("owner/plugin"), Some("7".into())),
+            Err(ErdError::NotFound { .. })
+        ));
+        match source.download(&artifact("owner/plugin"), Some("8".into())) {
+            Err(ErdError::ArtifactExpired { build_id, .. }) => assert_eq!(build_id, "8"),
+            _ => panic!("Expected the artifacts of run 8 to have expired"),
+        }
+        assert!(matches!(
+            source.history(&artifact("owner/private")),
+            Err(ErdError::Unauthorized { .. })
+        ));
+    }
+}
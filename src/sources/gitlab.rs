@@ -0,0 +1,1357 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{debug, info, warn};
+use regex::Regex;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::{StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::archive::find_artifact_in_archive;
+use crate::config::artifacts::{ArtifactConfig, Channel, SourceType};
+use crate::download::{Download, Resumable};
+use crate::output::ScannedProject;
+use crate::pattern::ArtifactMatcher;
+use crate::{ArtifactFiles, ErdError, FileData};
+
+use super::{ArtifactSource, BuildInfo, Capabilities, RemoteHash};
+use super::retry::{Retry, SendRetrying};
+
+#[derive(Deserialize)]
+pub struct ProjectData {
+    pub id: usize,
+    pub path_with_namespace: String,
+    pub web_url: String,
+}
+
+#[derive(Deserialize)]
+pub struct JobHistory {
+    pub id: usize,
+    pub status: String,
+    pub stage: String,
+    pub created_at: String,
+    pub name: String,
+    #[serde(rename = "ref")]
+    pub job_ref: String,
+    pub commit: JobCommit,
+    pub artifacts: Vec<JobArtifact>,
+    pub web_url: String,
+}
+
+impl JobHistory {
+    fn get_main_artifact(&self) -> Option<&JobArtifact> {
+        self.artifacts
+            .iter()
+            .find(|a| a.file_type == MAIN_ARTIFACT_TYPE)
+    }
+}
+
+/// When a job's artifacts expire, which is unset if it never had any or keeps them forever
+#[derive(Deserialize)]
+struct JobExpiry {
+    artifacts_expire_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct JobCommit {
+    pub short_id: String,
+    pub author_email: String,
+    pub title: String,
+}
+
+#[derive(Deserialize)]
+pub struct JobPipeline {
+    pub id: usize,
+    #[serde(rename = "ref")]
+    pub job_ref: String,
+    pub web_url: String,
+}
+
+const MAIN_ARTIFACT_TYPE: &str = "archive";
+#[derive(Deserialize)]
+pub struct JobArtifact {
+    file_type: String,
+    //size: usize,
+    //filename: String,
+    //file_format: String,
+}
+
+#[derive(Deserialize)]
+pub struct Package {
+    pub id: usize,
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub created_at: String,
+    #[serde(rename = "_links")]
+    pub links: Option<PackageLinks>,
+    #[serde(default)]
+    pub pipelines: Vec<PackagePipeline>,
+}
+
+#[derive(Deserialize)]
+pub struct PackageLinks {
+    pub web_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PackagePipeline {
+    #[serde(rename = "ref")]
+    pub job_ref: String,
+    pub sha: String,
+}
+
+#[derive(Deserialize)]
+pub struct PackageFile {
+    pub file_name: String,
+    pub file_sha256: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub released_at: String,
+    #[serde(default)]
+    pub upcoming_release: bool,
+    pub author: Option<ReleaseAuthor>,
+    pub commit: Option<ReleaseCommit>,
+    pub assets: ReleaseAssets,
+    #[serde(rename = "_links")]
+    pub links: Option<ReleaseLinks>,
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseAuthor {
+    pub username: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseCommit {
+    pub short_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseAssets {
+    #[serde(default)]
+    pub links: Vec<ReleaseLink>,
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseLink {
+    pub name: String,
+    pub url: String,
+    pub direct_asset_url: Option<String>,
+    /// The path of the asset below the release's permanent links, e.g. `/binaries/plugin.jar`
+    pub filepath: Option<String>,
+}
+
+impl ReleaseLink {
+    /// The name to save the asset as. The URL can't be used, as links often
+    /// point to storage objects named after a hash
+    fn get_file_name(&self) -> &str {
+        self.filepath
+            .as_deref()
+            .and_then(|path| path.rsplit('/').next())
+            .filter(|name| !name.is_empty())
+            .unwrap_or(&self.name)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseLinks {
+    #[serde(rename = "self")]
+    pub self_url: Option<String>,
+}
+
+impl Release {
+    fn find_asset(&self, matcher: &ArtifactMatcher) -> Result<Option<&ReleaseLink>, ErdError> {
+        matcher.select(&self.assets.links, |link| &link.name)
+    }
+}
+
+/// Packages that are still being uploaded, or have been hidden, have a different status
+const PACKAGE_STATUS_DEFAULT: &str = "default";
+
+/// The job used when an artifact does not specify one
+const DEFAULT_JOB: &str = "build";
+const STAGE_PREFIX: &str = "stage:";
+
+/// Which CI jobs of a pipeline produce an artifact
+pub enum JobSelector {
+    /// A job name, along with any parallel or matrix jobs created from it
+    Name(String),
+    Regex(Regex),
+    Stage(String),
+}
+
+impl JobSelector {
+    pub fn parse(artifact: &ArtifactConfig) -> Result<Self, ErdError> {
+        let job = artifact.job.as_deref().unwrap_or(DEFAULT_JOB);
+        if let Some(stage) = job.strip_prefix(STAGE_PREFIX) {
+            return Ok(JobSelector::Stage(stage.to_string()));
+        }
+        match job.strip_prefix('/').and_then(|j| j.strip_suffix('/')) {
+            Some(pattern) => Regex::new(pattern)
+                .map(JobSelector::Regex)
+                .map_err(|e| ErdError::InvalidArtifactConfig {
+                    artifact: artifact.id.clone(),
+                    desc: format!("Invalid job regex: {}", e),
+                }),
+            None => Ok(JobSelector::Name(job.to_string())),
+        }
+    }
+
+    pub fn matches(&self, job_name: &str, stage: &str) -> bool {
+        match self {
+            JobSelector::Name(name) => job_name == name || get_base_job_name(job_name) == name,
+            JobSelector::Regex(regex) => regex.is_match(job_name),
+            JobSelector::Stage(s) => stage == s,
+        }
+    }
+}
+
+/// Get the name of the job that a parallel (`build 1/3`) or matrix (`build: [linux, 1.20]`) job was created from
+fn get_base_job_name(job_name: &str) -> &str {
+    if let Some((base, matrix)) = job_name.split_once(": [") {
+        if matrix.ends_with(']') {
+            return base;
+        }
+    }
+    match job_name.rsplit_once(' ') {
+        Some((base, index)) if is_parallel_index(index) => base,
+        _ => job_name,
+    }
+}
+
+fn is_parallel_index(index: &str) -> bool {
+    match index.split_once('/') {
+        Some((n, total)) => {
+            !n.is_empty()
+                && !total.is_empty()
+                && n.chars().all(|c| c.is_ascii_digit())
+                && total.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// The path of the REST API, relative to the root URL of a GitLab instance
+const API_PATH: &str = "api/v4";
+
+const TOKEN_HEADER: HeaderName = HeaderName::from_static("private-token");
+/// Set by paginated endpoints to the number of the next page, or left empty on the last page
+const NEXT_PAGE_HEADER: &str = "x-next-page";
+/// Pipelines are scanned a page at a time, as the wanted one is usually among the first
+const PIPELINES_PER_PAGE: &str = "20";
+/// How many pages of pipelines to scan before giving up, so that a job that never ran
+/// on the branch doesn't walk its whole history
+const MAX_PIPELINE_PAGES: usize = 5;
+
+fn get_token_value(token: &str) -> Result<HeaderValue, ErdError> {
+    token
+        .parse()
+        .map_err(|_| ErdError::InvalidToken(token.to_string()))
+}
+
+/// Get the base URL of the REST API for the GitLab instance at the given URL.
+/// Instances served under a sub-path (e.g. https://example.com/gitlab/) keep their prefix.
+pub fn get_api_url(source_url: &str) -> String {
+    format!("{}/{}", source_url.trim_end_matches('/'), API_PATH)
+}
+
+/// Get the number of the page after the given response, if there is one
+fn get_next_page(response: &Response) -> Option<usize> {
+    response.headers().get(NEXT_PAGE_HEADER)?.to_str().ok()?.parse().ok()
+}
+
+/// Whether both URLs have the same scheme, host and port
+fn is_same_origin(url: &str, other: &str) -> bool {
+    match (Url::parse(url), Url::parse(other)) {
+        (Ok(url), Ok(other)) => {
+            url.scheme() == other.scheme()
+                && url.host_str() == other.host_str()
+                && url.port_or_known_default() == other.port_or_known_default()
+        }
+        _ => false,
+    }
+}
+
+/// The package file last found for each artifact, keyed by the artifact's id, with the version asked for.
+/// A later lookup for the same artifact replaces it, so entries that are never downloaded don't pile up
+type FoundPackageFiles = HashMap<String, (Option<String>, Option<(Package, PackageFile)>)>;
+
+/// Job artifacts from GitLab CI pipelines
+pub struct GitlabSource {
+    url: String,
+    token: Option<String>,
+    client: Client,
+    retry: Retry,
+    /// Package files found by [`remote_hash`](ArtifactSource::remote_hash),
+    /// kept for the download that follows it so that they aren't looked up twice
+    found_package_files: Mutex<FoundPackageFiles>,
+}
+
+impl GitlabSource {
+    pub fn new(url: String, token: Option<String>, client: Client, retry: Retry) -> Self {
+        Self {
+            url,
+            token,
+            client,
+            retry,
+            found_package_files: Mutex::default(),
+        }
+    }
+
+    fn check_status(&self, response: Response, what: &str) -> Result<Response, ErdError> {
+        super::check_status(SourceType::Gitlab, &self.url, response, what)
+    }
+
+    fn token_value(&self) -> Result<HeaderValue, ErdError> {
+        get_token_value(super::require_token(&self.token, &self.url)?)
+    }
+
+    /// List a page of the pipelines on the artifact's branch, most recent first,
+    /// along with the number of the next page if there is one
+    fn get_pipelines(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+        page: usize,
+    ) -> Result<(Vec<JobPipeline>, Option<usize>), ErdError> {
+        let url = format!("{}/projects/{}/pipelines", get_api_url(&self.url), artifact.project_id);
+        let response = client
+            .get(url)
+            .query(&[
+                ("ref", artifact.branch.as_str()),
+                ("order_by", "id"),
+                ("sort", "desc"),
+                ("per_page", PIPELINES_PER_PAGE),
+                ("page", &page.to_string()),
+            ])
+            .header(TOKEN_HEADER, self.token_value()?)
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get pipelines from Gitlab"))
+            .and_then(|r| self.check_status(r, "Received Error while getting pipelines"))?;
+        debug!("URL: {}", response.url());
+        let next_page = get_next_page(&response);
+        Ok((deserialize_response(response)?, next_page))
+    }
+
+    /// List the jobs of a pipeline that the selector matches
+    fn get_pipeline_jobs(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+        pipeline_id: usize,
+        selector: &JobSelector,
+    ) -> Result<Vec<JobHistory>, ErdError> {
+        let url = format!(
+            "{}/projects/{}/pipelines/{}/jobs",
+            get_api_url(&self.url),
+            artifact.project_id,
+            pipeline_id
+        );
+        let mut jobs = vec![];
+        let mut page = Some(1);
+        while let Some(p) = page {
+            let response = client
+                .get(&url)
+                .query(&[("per_page", "100"), ("page", &p.to_string())])
+                .header(TOKEN_HEADER, self.token_value()?)
+                .send_retrying(&self.retry)
+                .map_err(|e| request_failed(e, "Failed to get pipeline jobs from Gitlab"))
+                .and_then(|r| self.check_status(r, "Received Error while getting pipeline jobs"))?;
+            page = get_next_page(&response);
+            let page_jobs: Vec<JobHistory> = deserialize_response(response)?;
+            jobs.extend(page_jobs.into_iter().filter(|job| selector.matches(&job.name, &job.stage)));
+        }
+        Ok(jobs)
+    }
+
+    /// Find the selected jobs of the most recent pipeline in which they succeeded with artifacts,
+    /// looking through the latest [`MAX_PIPELINE_PAGES`] pages of pipelines of the branch.
+    /// Fails with [`ErdError::NoSuccessfulJob`] if there is no such pipeline among them
+    fn get_latest_jobs(&self, client: &Client, artifact: &ArtifactConfig) -> Result<Vec<JobHistory>, ErdError> {
+        let selector = JobSelector::parse(artifact)?;
+        let mut searched = 0;
+        let mut page = Some(1);
+        for _ in 0..MAX_PIPELINE_PAGES {
+            let Some(p) = page else { break };
+            let (pipelines, next_page) = self.get_pipelines(client, artifact, p)?;
+            searched += pipelines.len();
+            for pipeline in pipelines {
+                let jobs: Vec<JobHistory> = self
+                    .get_pipeline_jobs(client, artifact, pipeline.id, &selector)?
+                    .into_iter()
+                    .filter(|job| job.status == "success" && job.get_main_artifact().is_some())
+                    .collect();
+                if !jobs.is_empty() {
+                    debug!("Using pipeline {}", pipeline.id);
+                    return Ok(jobs);
+                }
+            }
+            page = next_page;
+        }
+        Err(ErdError::NoSuccessfulJob {
+            artifact: artifact.id.clone(),
+            branch: artifact.branch.clone(),
+            searched,
+        })
+    }
+
+    /// Download the artifacts of the latest successful job with the given name on the artifact's branch,
+    /// in a single request. Returns `None` if GitLab has no such job, e.g. when only parallel
+    /// or matrix jobs were created from the name
+    fn get_latest_artifact_by_ref(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+        job: &str,
+    ) -> Result<Option<Download>, ErdError> {
+        let url = format!(
+            "{}/projects/{}/jobs/artifacts/{}/download",
+            get_api_url(&self.url),
+            artifact.project_id,
+            artifact.branch.replace('/', "%2F")
+        );
+        let request = client
+            .get(url)
+            .query(&[("job", job)])
+            .header(TOKEN_HEADER, self.token_value()?);
+        let (request, resumable) = Resumable::prepare(request);
+        let response = request
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get artifact Gitlab"))?;
+        debug!("URL: {}", response.url());
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = self.check_status(response, "Received Error while downloading artifact zip")?;
+        let download = resumable.finish(response, "Failed to read data from artifact zip")?;
+        debug!("{} bytes read", download.len());
+        Ok(Some(download))
+    }
+
+    fn get_artifact_version(&self, artifact: &ArtifactConfig, build_id: &str) -> Result<Download, ErdError> {
+        let url = format!(
+            "{}/projects/{}/jobs/{}/artifacts",
+            get_api_url(&self.url),
+            artifact.project_id,
+            build_id
+        );
+        let client = &self.client;
+        let (request, resumable) = Resumable::prepare(client.get(url).header(TOKEN_HEADER, self.token_value()?));
+        let response = request
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get artifact Gitlab"))?;
+        // Gitlab gives the same 404 for a missing job, one without artifacts and one whose artifacts have expired
+        if response.status() == StatusCode::NOT_FOUND && self.artifacts_expired(client, artifact, build_id)? {
+            return Err(ErdError::ArtifactExpired {
+                artifact: artifact.id.clone(),
+                build_id: build_id.to_string(),
+            });
+        }
+        let response = self.check_status(response, "Received Error while downloading artifact zip")?;
+        let download = resumable.finish(response, "Failed to read data from artifact zip")?;
+        debug!("{} bytes read", download.len());
+        Ok(download)
+    }
+
+    /// Whether the given job exists and had artifacts that have since expired
+    fn artifacts_expired(&self, client: &Client, artifact: &ArtifactConfig, build_id: &str) -> Result<bool, ErdError> {
+        let url = format!("{}/projects/{}/jobs/{}", get_api_url(&self.url), artifact.project_id, build_id);
+        let response = client
+            .get(url)
+            .header(TOKEN_HEADER, self.token_value()?)
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get job from Gitlab"))?;
+        if !response.status().is_success() {
+            return Ok(false);
+        }
+        let job: JobExpiry = deserialize_response(response)?;
+        Ok(job
+            .artifacts_expire_at
+            .and_then(|t| OffsetDateTime::parse(&t, &Rfc3339).ok())
+            .is_some_and(|t| t <= OffsetDateTime::now_utc()))
+    }
+
+    /// Download a single file from job artifacts, given the URL of the artifacts
+    /// (either `jobs/:id/artifacts` or `jobs/artifacts/:ref/raw`).
+    /// Returns `None` if there is no such job or file
+    fn get_artifact_file(
+        &self,
+        client: &Client,
+        artifacts_url: &str,
+        job: Option<&str>,
+        path: &str,
+    ) -> Result<Option<Download>, ErdError> {
+        let mut url = Url::parse(artifacts_url).map_err(|_| ErdError::SourceRequestError {
+            source: SourceType::Gitlab,
+            url: artifacts_url.to_string(),
+            desc: "Invalid Gitlab URL".to_string(),
+        })?;
+        url.path_segments_mut()
+            .expect("HTTP URLs have a path")
+            .extend(path.split('/').filter(|s| !s.is_empty()));
+        let mut request = client.get(url).header(TOKEN_HEADER, self.token_value()?);
+        if let Some(job) = job {
+            request = request.query(&[("job", job)]);
+        }
+        let (request, resumable) = Resumable::prepare(request);
+        let response = request
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get artifact file from Gitlab"))?;
+        debug!("URL: {}", response.url());
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = self.check_status(response, "Received Error while downloading artifact file")?;
+        let download = resumable.finish(response, "Failed to read data from artifact file")?;
+        debug!("{} bytes read", download.len());
+        Ok(Some(download))
+    }
+
+    /// Download only the file at the artifact's `artifact_path`, rather than the whole archive
+    fn download_artifact_file(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<&str>,
+        path: &str,
+    ) -> Result<Option<ArtifactFiles>, ErdError> {
+        let file_name = Path::new(path)
+            .file_name()
+            .ok_or_else(|| ErdError::InvalidArtifactConfig {
+                artifact: artifact.id.clone(),
+                desc: format!("artifact_path '{}' is not a file", path),
+            })?;
+        let client = &self.client;
+        let api_url = get_api_url(&self.url);
+        let data = match build_id {
+            Some(b_id) => {
+                let url = format!("{}/projects/{}/jobs/{}/artifacts", api_url, artifact.project_id, b_id);
+                self.get_artifact_file(client, &url, None, path)?
+            }
+            None => self.get_latest_artifact_file(client, artifact, path)?,
+        };
+        Ok(data.map(|data| {
+            FileData {
+                file_name: file_name.into(),
+                data,
+                mode: None,
+            }
+            .into()
+        }))
+    }
+
+    fn get_latest_artifact_file(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+        path: &str,
+    ) -> Result<Option<Download>, ErdError> {
+        let api_url = get_api_url(&self.url);
+        // The ref endpoint only takes an exact job name, so parallel and matrix jobs
+        // (or other selectors) need their job ids looking up instead
+        if let JobSelector::Name(name) = JobSelector::parse(artifact)? {
+            let url = format!(
+                "{}/projects/{}/jobs/artifacts/{}/raw",
+                api_url,
+                artifact.project_id,
+                artifact.branch.replace('/', "%2F")
+            );
+            if let Some(data) = self.get_artifact_file(client, &url, Some(&name), path)? {
+                return Ok(Some(data));
+            }
+        }
+        for job in self.get_latest_jobs(client, artifact)? {
+            debug!("Looking for {} in artifacts of job {} ({})", path, job.name, job.id);
+            let url = format!("{}/projects/{}/jobs/{}/artifacts", api_url, artifact.project_id, job.id);
+            if let Some(data) = self.get_artifact_file(client, &url, None, path)? {
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl GitlabSource {
+    /// List the versions of a generic package, newest first
+    fn get_packages(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+        name: &str,
+        version: Option<&str>,
+    ) -> Result<Vec<Package>, ErdError> {
+        let url = format!("{}/projects/{}/packages", get_api_url(&self.url), artifact.project_id);
+        let mut query = vec![
+            ("package_type", "generic"),
+            ("package_name", name),
+            ("order_by", "created_at"),
+            ("sort", "desc"),
+            ("per_page", "20"),
+        ];
+        if let Some(version) = version {
+            query.push(("package_version", version));
+        }
+        let response = client
+            .get(url)
+            .query(&query)
+            .header(TOKEN_HEADER, self.token_value()?)
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get packages from Gitlab"))
+            .and_then(|r| self.check_status(r, "Received Error while getting packages"))?;
+        debug!("URL: {}", response.url());
+        let packages: Vec<Package> = deserialize_response(response)?;
+        // The package_name filter also matches packages that only contain the name
+        Ok(packages
+            .into_iter()
+            .filter(|p| p.name == name && version.is_none_or(|v| p.version == v))
+            .collect())
+    }
+
+    /// Find the file of a generic package that matches the artifact's pattern,
+    /// in the given version or the latest one
+    fn find_package_file(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+        name: &str,
+        version: Option<&str>,
+    ) -> Result<Option<(Package, PackageFile)>, ErdError> {
+        let package = self
+            .get_packages(client, artifact, name, version)?
+            .into_iter()
+            .find(|p| p.status.is_empty() || p.status == PACKAGE_STATUS_DEFAULT);
+        let package = match package {
+            Some(package) => package,
+            None => {
+                warn!("No published versions of package {} were found", name);
+                return Ok(None);
+            }
+        };
+        debug!("Using package {} version {}", package.name, package.version);
+        let url = format!(
+            "{}/projects/{}/packages/{}/package_files",
+            get_api_url(&self.url),
+            artifact.project_id,
+            package.id
+        );
+        let response = client
+            .get(url)
+            .query(&[("per_page", "100")])
+            .header(TOKEN_HEADER, self.token_value()?)
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get package files from Gitlab"))
+            .and_then(|r| self.check_status(r, "Received Error while getting package files"))?;
+        let files: Vec<PackageFile> = deserialize_response(response)?;
+        // Files are listed oldest first, and re-uploading a file keeps the old one
+        let file = ArtifactMatcher::new(artifact)?.select(files, |f| &f.file_name)?;
+        Ok(file.map(|f| (package, f)))
+    }
+
+    fn download_package(
+        &self,
+        artifact: &ArtifactConfig,
+        name: &str,
+        version: Option<&str>,
+    ) -> Result<Option<ArtifactFiles>, ErdError> {
+        let client = &self.client;
+        let found = self
+            .found_package_files
+            .lock()
+            .expect("Package files lock was poisoned")
+            .remove(&artifact.id)
+            .filter(|(found_version, _)| found_version.as_deref() == version);
+        let found = match found {
+            Some((_, found)) => found,
+            None => self.find_package_file(client, artifact, name, version)?,
+        };
+        let (package, file) = match found {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let api_url = get_api_url(&self.url);
+        let mut url = Url::parse(&api_url).map_err(|_| ErdError::SourceRequestError {
+            source: SourceType::Gitlab,
+            url: api_url.clone(),
+            desc: "Invalid Gitlab URL".to_string(),
+        })?;
+        // Package names, versions and file names may hold slashes or other reserved characters
+        url.path_segments_mut()
+            .expect("HTTP URLs have a path")
+            .extend(["projects", &artifact.project_id, "packages", "generic"])
+            .extend([&package.name, &package.version, &file.file_name]);
+        let (request, resumable) = Resumable::prepare(client.get(url).header(TOKEN_HEADER, self.token_value()?));
+        let response = request
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get package file from Gitlab"))
+            .and_then(|r| self.check_status(r, "Received Error while downloading package file"))?;
+        let download = resumable.finish(response, "Failed to read data from package file")?;
+        debug!("{} bytes read", download.len());
+        Ok(Some(FileData {
+            file_name: file.file_name.into(),
+            data: download,
+            mode: None,
+        }
+        .into()))
+    }
+
+    fn package_history(&self, artifact: &ArtifactConfig, name: &str) -> Result<Vec<BuildInfo>, ErdError> {
+        let client = &self.client;
+        Ok(self
+            .get_packages(client, artifact, name, None)?
+            .into_iter()
+            .take(6)
+            .map(|package| package.into_build_info(&self.url))
+            .collect())
+    }
+}
+
+impl GitlabSource {
+    /// List the project's releases, most recently released first
+    fn get_releases(&self, client: &Client, artifact: &ArtifactConfig, per_page: &str) -> Result<Vec<Release>, ErdError> {
+        let url = format!("{}/projects/{}/releases", get_api_url(&self.url), artifact.project_id);
+        let response = client
+            .get(url)
+            .query(&[("order_by", "released_at"), ("sort", "desc"), ("per_page", per_page)])
+            .header(TOKEN_HEADER, self.token_value()?)
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get releases from Gitlab"))
+            .and_then(|r| self.check_status(r, "Received Error while getting releases"))?;
+        debug!("URL: {}", response.url());
+        deserialize_response(response)
+    }
+
+    fn get_release(&self, client: &Client, artifact: &ArtifactConfig, tag: &str) -> Result<Release, ErdError> {
+        let url = format!(
+            "{}/projects/{}/releases/{}",
+            get_api_url(&self.url),
+            artifact.project_id,
+            tag.replace('/', "%2F")
+        );
+        let response = client
+            .get(url)
+            .header(TOKEN_HEADER, self.token_value()?)
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get release from Gitlab"))
+            .and_then(|r| self.check_status(r, "Received Error while getting release"))?;
+        deserialize_response(response)
+    }
+
+    fn download_release(&self, artifact: &ArtifactConfig, tag: Option<&str>) -> Result<Option<ArtifactFiles>, ErdError> {
+        let client = &self.client;
+        let release = match tag {
+            Some(tag) => self.get_release(client, artifact, tag)?,
+            None => {
+                let latest = self
+                    .get_releases(client, artifact, "20")?
+                    .into_iter()
+                    .find(|r| !r.upcoming_release);
+                match latest {
+                    Some(release) => release,
+                    None => {
+                        warn!("No releases of {} were found", artifact.project_id);
+                        return Ok(None);
+                    }
+                }
+            }
+        };
+        debug!("Using release {}", release.tag_name);
+        let link = match release.find_asset(&ArtifactMatcher::new(artifact)?)? {
+            Some(link) => link,
+            None => {
+                warn!("Release {} has no asset matching the artifact", release.tag_name);
+                return Ok(None);
+            }
+        };
+        let url = link.direct_asset_url.as_deref().unwrap_or(&link.url);
+        debug!("Downloading asset {} from {}", link.name, url);
+        let mut request = client.get(url);
+        // Assets can be hosted anywhere, so only send the token back to GitLab
+        if is_same_origin(url, &self.url) {
+            request = request.header(TOKEN_HEADER, self.token_value()?);
+        }
+        let (request, resumable) = Resumable::prepare(request);
+        let response = request
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get release asset"))
+            .and_then(|r| self.check_status(r, "Received Error while downloading release asset"))?;
+        let file_name = link.get_file_name().to_string();
+        let download = resumable.finish(response, "Failed to read data from release asset")?;
+        debug!("{} bytes read", download.len());
+        Ok(Some(FileData {
+            file_name: file_name.into(),
+            data: download,
+            mode: None,
+        }
+        .into()))
+    }
+
+    fn release_history(&self, artifact: &ArtifactConfig) -> Result<Vec<BuildInfo>, ErdError> {
+        let client = &self.client;
+        let matcher = ArtifactMatcher::new(artifact)?;
+        Ok(self
+            .get_releases(client, artifact, "6")?
+            .into_iter()
+            .map(|release| release.into_build_info(&matcher))
+            .collect())
+    }
+}
+
+impl ArtifactSource for GitlabSource {
+    fn kind(&self) -> SourceType {
+        SourceType::Gitlab
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            scan: true,
+            history: true,
+            rebuild: true,
+        }
+    }
+
+    fn scan(&self, query: Option<String>) -> Result<Vec<ScannedProject>, ErdError> {
+        let client = &self.client;
+        let token_value: Option<HeaderValue> = self.token.as_deref().map(get_token_value).transpose()?;
+        // https://docs.gitlab.com/ee/api/projects.html#list-all-projects
+        // TODO: filter by owned, group, etc.
+        let url = format!("{}/projects", get_api_url(&self.url));
+        let mut request = client
+            .get(url)
+            .query(&[
+                ("membership", "true"),
+                ("order_by", "last_activity_at"),
+                ("per_page", "30"),
+                ("search", query.as_deref().unwrap_or("")),
+                ("search_namespaces", "true"),
+            ]);
+        if let Some(h_value) = token_value {
+            request = request.header(TOKEN_HEADER, h_value);
+        }
+        else {
+            warn!("Scanning without login - you might not get any results.");
+        }
+        let response = request.send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get project list"))?;
+        let response = self.check_status(response, "Received Error while getting project list")?;
+        debug!("Got HTTP Code {}", response.status());
+        let projects: Vec<ProjectData> = deserialize_response(response)?;
+        Ok(projects.into_iter().map(ScannedProject::from).collect())
+    }
+
+    fn remote_hash(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<&str>,
+    ) -> Result<Option<RemoteHash>, ErdError> {
+        let name = match &artifact.channel {
+            Some(Channel::Package { name }) => name,
+            Some(Channel::Release) | None => return Ok(None),
+        };
+        let client = &self.client;
+        let found = self.find_package_file(client, artifact, name, build_id)?;
+        let remote_hash = found.as_ref().and_then(|(_, file)| {
+            let sha256 = super::parse_hex(file.file_sha256.as_deref()?)?;
+            Some(RemoteHash {
+                file_name: PathBuf::from(&file.file_name),
+                sha256,
+            })
+        });
+        self.found_package_files
+            .lock()
+            .expect("Package files lock was poisoned")
+            .insert(artifact.id.clone(), (build_id.map(str::to_string), found));
+        Ok(remote_hash)
+    }
+
+    fn latest_build_id(&self, artifact: &ArtifactConfig) -> Result<Option<String>, ErdError> {
+        // Packages can have files added to a version, and exact job names are downloaded
+        // in one request through the ref endpoint, which doesn't say which job it used
+        if artifact.channel.is_some() {
+            return Ok(None);
+        }
+        if let JobSelector::Name(_) = JobSelector::parse(artifact)? {
+            return Ok(None);
+        }
+        // Parallel and matrix jobs have an artifact each, which a single build id can't cover
+        match self.get_latest_jobs(&self.client, artifact)?.as_slice() {
+            [job] => Ok(Some(job.id.to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    fn download(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<String>,
+    ) -> Result<Option<ArtifactFiles>, ErdError> {
+        match &artifact.channel {
+            Some(Channel::Package { name }) => return self.download_package(artifact, name, build_id.as_deref()),
+            Some(Channel::Release) => return self.download_release(artifact, build_id.as_deref()),
+            None => {}
+        }
+        // Directories and inner archives need the whole archive
+        if artifact.directories.is_empty() && artifact.inner_archive.is_none() {
+            if let Some(path) = &artifact.artifact_path {
+                return self.download_artifact_file(artifact, build_id.as_deref(), path);
+            }
+        }
+        if let Some(b_id) = build_id {
+            let download = self.get_artifact_version(artifact, &b_id)?;
+            return find_artifact_in_archive(download, artifact);
+        }
+        let client = &self.client;
+        // The ref endpoint only takes an exact job name, so parallel and matrix jobs
+        // (or other selectors) need their pipelines scanning instead
+        if let JobSelector::Name(name) = JobSelector::parse(artifact)? {
+            if let Some(download) = self.get_latest_artifact_by_ref(client, artifact, &name)? {
+                return find_artifact_in_archive(download, artifact);
+            }
+            debug!("No job named {} on {}, looking for parallel or matrix jobs", name, artifact.branch);
+        }
+        let jobs = self.get_latest_jobs(client, artifact)?;
+        // Parallel and matrix jobs each have their own artifacts, so look through all of them
+        for job in jobs {
+            debug!("Looking in artifacts of job {} ({})", job.name, job.id);
+            let download = self.get_artifact_version(artifact, &job.id.to_string())?;
+            if let Some(file_data) = find_artifact_in_archive(download, artifact)? {
+                return Ok(Some(file_data));
+            }
+        }
+        Ok(None)
+    }
+
+    fn history(&self, artifact: &ArtifactConfig) -> Result<Vec<BuildInfo>, ErdError> {
+        match &artifact.channel {
+            Some(Channel::Package { name }) => return self.package_history(artifact, name),
+            Some(Channel::Release) => return self.release_history(artifact),
+            None => {}
+        }
+        let client = &self.client;
+        let selector = JobSelector::parse(artifact)?;
+        let mut builds = vec![];
+        let (pipelines, _) = self.get_pipelines(client, artifact, 1)?;
+        for pipeline in pipelines {
+            let jobs = self.get_pipeline_jobs(client, artifact, pipeline.id, &selector)?;
+            builds.extend(jobs.into_iter().map(BuildInfo::from));
+            if builds.len() >= 6 {
+                break;
+            }
+        }
+        builds.truncate(6);
+        Ok(builds)
+    }
+
+    fn rebuild(&self, artifact: &ArtifactConfig, build_id: String) -> Result<(), ErdError> {
+        let client = &self.client;
+        let token_value = self.token_value()?;
+        let api_url = get_api_url(&self.url);
+        let create_pipeline_url = format!(
+            "{}/projects/{}/pipeline",
+            api_url, artifact.project_id,
+        );
+        let create_pipeline_response = client
+            .post(&create_pipeline_url)
+            .header(TOKEN_HEADER, token_value.clone())
+            .query(&[("ref", &build_id)])
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, &format!("Failed to retry job {} on Gitlab", build_id)))
+            .and_then(|r| self.check_status(r, "Received Error while creating pipeline"))?;
+        let new_pipeline: JobPipeline = deserialize_response(create_pipeline_response)?;
+        info!(
+            "Started pipeline {} to rebuild {}",
+            new_pipeline.id, build_id
+        );
+        let list_jobs_url = format!(
+            "{}/projects/{}/pipelines/{}/jobs",
+            api_url, artifact.project_id, new_pipeline.id
+        );
+        let list_jobs_response = client
+            .get(&list_jobs_url)
+            .header(TOKEN_HEADER, token_value)
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to list jobs for created pipeline"))
+            .and_then(|r| self.check_status(r, "Received Error while listing jobs for created pipeline"))?;
+        let pipeline_jobs: Vec<JobHistory> = deserialize_response(list_jobs_response)?;
+        match pipeline_jobs.first() {
+            Some(job) => {
+                info!(
+                    "> {} ({}) - {}",
+                    job.commit.short_id, new_pipeline.job_ref, job.commit.title
+                );
+            }
+            None => {
+                warn!("No jobs appear to have been started");
+            }
+        }
+        for job in pipeline_jobs {
+            info!("> Started job {} ({}) - {}", job.name, job.id, job.web_url);
+        }
+        info!("> {}", new_pipeline.job_ref);
+        info!(
+            "> New pipeline {} - {}",
+            new_pipeline.id, new_pipeline.web_url
+        );
+        info!("Check the job history to see when the pipeline is complete and its job id");
+        Ok(())
+    }
+}
+
+fn deserialize_response<T: DeserializeOwned>(response: Response) -> Result<T, ErdError> {
+    super::deserialize_response(SourceType::Gitlab, response)
+}
+
+fn request_failed(error: reqwest::Error, what: &str) -> ErdError {
+    super::request_failed(SourceType::Gitlab, error, what)
+}
+
+impl From<ProjectData> for ScannedProject {
+    fn from(project: ProjectData) -> Self {
+        ScannedProject {
+            path: project.path_with_namespace,
+            id: project.id.to_string(),
+            url: project.web_url,
+        }
+    }
+}
+
+impl From<JobHistory> for BuildInfo {
+    fn from(job: JobHistory) -> Self {
+        let has_artifacts = job.get_main_artifact().is_some();
+        BuildInfo {
+            id: job.id.to_string(),
+            job_ref: job.job_ref,
+            timestamp: job.created_at,
+            status: job.status,
+            has_artifacts,
+            web_url: job.web_url,
+            commit_short_id: job.commit.short_id,
+            commit_title: job.commit.title,
+            commit_author: job.commit.author_email,
+            notes: String::new(),
+        }
+    }
+}
+
+impl Package {
+    fn into_build_info(self, source_url: &str) -> BuildInfo {
+        let pipeline = self.pipelines.into_iter().next();
+        let web_path = self.links.and_then(|l| l.web_path).unwrap_or_default();
+        BuildInfo {
+            id: self.version,
+            job_ref: pipeline.as_ref().map(|p| p.job_ref.clone()).unwrap_or_default(),
+            timestamp: self.created_at,
+            status: match self.status.as_str() {
+                "" | PACKAGE_STATUS_DEFAULT => "available".to_string(),
+                status => status.to_string(),
+            },
+            has_artifacts: true,
+            web_url: format!("{}{}", source_url.trim_end_matches('/'), web_path),
+            commit_short_id: pipeline.map(|p| p.sha.chars().take(8).collect()).unwrap_or_default(),
+            commit_title: String::new(),
+            commit_author: String::new(),
+            notes: String::new(),
+        }
+    }
+}
+
+impl Release {
+    fn into_build_info(self, matcher: &ArtifactMatcher) -> BuildInfo {
+        let has_artifacts = self.assets.links.iter().any(|link| matcher.matches(&link.name));
+        BuildInfo {
+            id: self.tag_name,
+            job_ref: self.name,
+            timestamp: self.released_at,
+            status: match self.upcoming_release {
+                true => "upcoming".to_string(),
+                false => "released".to_string(),
+            },
+            has_artifacts,
+            web_url: self.links.and_then(|l| l.self_url).unwrap_or_default(),
+            commit_short_id: self.commit.map(|c| c.short_id).unwrap_or_default(),
+            commit_title: String::new(),
+            commit_author: self.author.map(|a| a.username).unwrap_or_default(),
+            notes: self.description.unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sources::test_server::{assert_downloads, serve, serve_with, test_artifact, zip_of, Route};
+
+    const TOKEN_HEADER_LINE: &str = "PRIVATE-TOKEN: token";
+
+    #[test]
+    fn test_api_url() {
+        assert_eq!(get_api_url("https://gitlab.com/"), "https://gitlab.com/api/v4");
+        assert_eq!(get_api_url("https://gitlab.com"), "https://gitlab.com/api/v4");
+        assert_eq!(
+            get_api_url("https://example.com/gitlab/"),
+            "https://example.com/gitlab/api/v4"
+        );
+        assert_eq!(
+            get_api_url("http://gitlab.internal:8080"),
+            "http://gitlab.internal:8080/api/v4"
+        );
+    }
+
+    #[test]
+    fn test_same_origin() {
+        assert!(is_same_origin("https://gitlab.com/group/project/-/releases/v1/downloads/a.jar", "https://gitlab.com/"));
+        assert!(is_same_origin("https://gitlab.com:443/a.jar", "https://gitlab.com"));
+        assert!(!is_same_origin("https://gitlab.com.evil.example/a.jar", "https://gitlab.com"));
+        assert!(!is_same_origin("http://gitlab.com/a.jar", "https://gitlab.com"));
+        assert!(!is_same_origin("https://gitlab.com:8443/a.jar", "https://gitlab.com"));
+    }
+
+    #[test]
+    fn test_job_selector() {
+        let selector = |job: &str| {
+            JobSelector::parse(&ArtifactConfig {
+                job: Some(job.into()),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let build = selector("build");
+        assert!(build.matches("build", "build"));
+        assert!(build.matches("build 2/3", "build"));
+        assert!(build.matches("build: [linux, 1.20]", "build"));
+        assert!(!build.matches("build-docs", "build"));
+        assert!(!build.matches("build linux", "build"));
+
+        assert!(selector("build: [linux, 1.20]").matches("build: [linux, 1.20]", "build"));
+        assert!(!selector("build: [linux, 1.20]").matches("build: [windows, 1.20]", "build"));
+
+        let regex = selector("/^(package|assemble)/");
+        assert!(regex.matches("package", "build"));
+        assert!(regex.matches("assemble 1/2", "build"));
+        assert!(!regex.matches("test", "build"));
+
+        assert!(selector("stage:assemble").matches("shadow-jar", "assemble"));
+        assert!(!selector("stage:assemble").matches("assemble", "test"));
+    }
+
+    #[test]
+    fn test_download_latest_job() {
+        let zip = zip_of(&[("build/libs/plugin-1.0.jar", b"jar contents")]);
+        let job = |id: usize, status: &str| {
+            format!(
+                r#"{{"id": {}, "status": "{}", "stage": "assemble", "created_at": "", "name": "shadow-jar", "ref": "main",
+                "commit": {{"short_id": "", "author_email": "", "title": ""}},
+                "artifacts": [{{"file_type": "archive", "filename": "artifacts.zip", "size": 1}}], "web_url": ""}}"#,
+                id, status
+            )
+        };
+        let url = serve(
+            vec![
+                Route::new("/api/v4/projects/42/jobs/artifacts/main/download", zip.clone()),
+                // The second page of pipelines has the last successful job
+                Route::new("/api/v4/projects/43/pipelines", r#"[{"id": 11, "ref": "main", "web_url": ""}]"#)
+                    .with_header("X-Next-Page: 2")
+                    .times(1),
+                Route::new("/api/v4/projects/43/pipelines", r#"[{"id": 10, "ref": "main", "web_url": ""}]"#)
+                    .with_header("X-Next-Page: "),
+                Route::new("/api/v4/projects/43/pipelines/11/jobs", format!("[{}]", job(111, "failed"))),
+                Route::new("/api/v4/projects/43/pipelines/10/jobs", format!("[{}]", job(100, "success"))),
+                Route::new("/api/v4/projects/43/jobs/100/artifacts", zip),
+            ]
+            .into_iter()
+            .map(|route| route.requiring_header(TOKEN_HEADER_LINE))
+            .collect(),
+        );
+
+        let source = GitlabSource::new(url, Some("token".into()), Client::new(), Retry::default());
+        let artifact = |project_id: &str, job: &str| ArtifactConfig {
+            project_id: project_id.into(),
+            job: Some(job.into()),
+            ..test_artifact(".jar")
+        };
+        // Exact job names use the ref endpoint without scanning pipelines
+        for artifact in [artifact("42", "build"), artifact("43", "stage:assemble")] {
+            assert_downloads(&source, &artifact, None, "plugin-1.0.jar", b"jar contents");
+        }
+    }
+
+    #[test]
+    fn test_download_package() {
+        let packages = r#"[
+            {"id": 5, "name": "plugin-extra", "version": "3.0.0", "status": "default"},
+            {"id": 4, "name": "plugin", "version": "2.0.0", "status": "processing"},
+            {"id": 3, "name": "plugin", "version": "1.1.0", "status": "default"}
+        ]"#;
+        let files = r#"[
+            {"id": 1, "file_name": "plugin-1.1.0.jar", "file_sha256": null},
+            {"id": 2, "file_name": "plugin-1.1.0.pom", "file_sha256": null}
+        ]"#;
+        // The package is only looked up once, for both its hash and its download
+        let url = serve(vec![
+            Route::new("/api/v4/projects/42/packages", packages).times(1),
+            Route::new("/api/v4/projects/42/packages/3/package_files", files).times(1),
+            Route::new("/api/v4/projects/42/packages/generic/plugin/1.1.0/plugin-1.1.0.jar", "jar contents"),
+        ]);
+
+        let source = GitlabSource::new(url, Some("token".into()), Client::new(), Retry::default());
+        let artifact = ArtifactConfig {
+            project_id: "42".into(),
+            channel: Some(Channel::Package { name: "plugin".into() }),
+            ..test_artifact(".jar")
+        };
+        assert!(source.remote_hash(&artifact, None).unwrap().is_none());
+        assert_downloads(&source, &artifact, None, "plugin-1.1.0.jar", b"jar contents");
+    }
+
+    #[test]
+    fn test_package_files_found_are_replaced() {
+        let packages = r#"[{"id": 3, "name": "plugin", "version": "1.1.0", "status": "default"}]"#;
+        let files = r#"[{"id": 1, "file_name": "plugin-1.1.0.jar", "file_sha256": null}]"#;
+        let url = serve(vec![
+            Route::new("/api/v4/projects/42/packages", packages),
+            Route::new("/api/v4/projects/42/packages/3/package_files", files),
+        ]);
+
+        let source = GitlabSource::new(url, Some("token".into()), Client::new(), Retry::default());
+        let artifact = ArtifactConfig {
+            project_id: "42".into(),
+            channel: Some(Channel::Package { name: "plugin".into() }),
+            ..test_artifact(".jar")
+        };
+        // Up-to-date fetches never download what they looked up
+        for version in [None, Some("1.1.0"), None] {
+            source.remote_hash(&artifact, version).unwrap();
+        }
+        let found = source.found_package_files.lock().unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found["plugin"].0, None);
+    }
+
+    #[test]
+    fn test_download_package_with_reserved_characters() {
+        let packages = r#"[{"id": 3, "name": "tools/plugin", "version": "1.1.0+build 5", "status": "default"}]"#;
+        let files = r#"[{"id": 1, "file_name": "plugin 1.1.0.jar", "file_sha256": null}]"#;
+        let url = serve(vec![
+            Route::new("/api/v4/projects/42/packages", packages),
+            Route::new("/api/v4/projects/42/packages/3/package_files", files),
+            Route::new(
+                "/api/v4/projects/42/packages/generic/tools%2Fplugin/1.1.0+build%205/plugin%201.1.0.jar",
+                "jar contents",
+            ),
+        ]);
+
+        let source = GitlabSource::new(url, Some("token".into()), Client::new(), Retry::default());
+        let artifact = ArtifactConfig {
+            project_id: "42".into(),
+            channel: Some(Channel::Package { name: "tools/plugin".into() }),
+            ..test_artifact(".jar")
+        };
+        assert_downloads(&source, &artifact, None, "plugin 1.1.0.jar", b"jar contents");
+    }
+
+    #[test]
+    fn test_download_latest_release() {
+        let url = serve_with(|url| {
+            let releases = format!(
+                r#"[
+                {{"tag_name": "v2.0", "upcoming_release": true, "assets": {{"links": []}}}},
+                {{"tag_name": "v1.0", "name": "1.0", "description": "Fixed things", "upcoming_release": false,
+                  "assets": {{"links": [
+                    {{"name": "sources.zip", "url": "https://example.com/sources.zip"}},
+                    {{"name": "plugin-1.0.jar", "url": "{}/uploads/9f86d081", "filepath": "/binaries/plugin-1.0-all.jar"}}
+                  ]}}}}
+            ]"#,
+                url
+            );
+            vec![
+                Route::new("/api/v4/projects/42/releases", releases),
+                // Links to GitLab itself need the token, e.g. for uploads to private projects
+                Route::new("/uploads/9f86d081", "release jar").requiring_header(TOKEN_HEADER_LINE),
+            ]
+        });
+
+        let source = GitlabSource::new(url, Some("token".into()), Client::new(), Retry::default());
+        let artifact = ArtifactConfig {
+            project_id: "42".into(),
+            channel: Some(Channel::Release),
+            ..test_artifact(".jar")
+        };
+        // The asset is named after its permanent link rather than the upload's hash
+        assert_downloads(&source, &artifact, None, "plugin-1.0-all.jar", b"release jar");
+    }
+
+    #[test]
+    fn test_download_artifact_path() {
+        let url = serve(vec![
+            Route::new("/api/v4/projects/42/jobs/artifacts/feature%2Fsponge/raw/build/libs/plugin.jar", "latest jar"),
+            Route::new("/api/v4/projects/42/jobs/7/artifacts/build/libs/plugin.jar", "jar of job 7"),
+        ]);
+
+        let source = GitlabSource::new(url, Some("token".into()), Client::new(), Retry::default());
+        let artifact = ArtifactConfig {
+            project_id: "42".into(),
+            branch: "feature/sponge".into(),
+            artifact_path: Some("build/libs/plugin.jar".into()),
+            ..test_artifact(".jar")
+        };
+        // Branches are escaped as a single path segment of the ref endpoint
+        assert_downloads(&source, &artifact, None, "plugin.jar", b"latest jar");
+        assert_downloads(&source, &artifact, Some("7"), "plugin.jar", b"jar of job 7");
+    }
+
+    #[test]
+    fn test_download_errors() {
+        let url = serve(vec![
+            Route::new("/api/v4/projects/42/jobs/7", r#"{"artifacts_expire_at": "2020-01-01T00:00:00.000Z"}"#),
+            // Job 9 never produced artifacts
+            Route::new("/api/v4/projects/42/jobs/9", r#"{"artifacts_expire_at": null}"#),
+            Route::new("/api/v4/projects/42/pipelines", "[]"),
+            Route::new("/api/v4/projects/43/pipelines", "{}").with_status("401 Unauthorized"),
+            // Every page links to another, so only the page limit ends the scan
+            Route::new("/api/v4/projects/44/pipelines", r#"[{"id": 1, "ref": "main", "web_url": ""}]"#)
+                .with_header("X-Next-Page: 2")
+                .times(MAX_PIPELINE_PAGES),
+            Route::new("/api/v4/projects/44/pipelines/1/jobs", "[]"),
+        ]);
+        let source = GitlabSource::new(url, Some("token".into()), Client::new(), Retry::default());
+        let artifact = |project_id: &str| ArtifactConfig {
+            project_id: project_id.into(),
+            ..test_artifact(".jar")
+        };
+        match source.download(&artifact("42"), Some("7".into())) {
+            Err(ErdError::ArtifactExpired { build_id, .. }) => assert_eq!(build_id, "7"),
+            _ => panic!("Expected the artifacts of job 7 to have expired"),
+        }
+        for build_id in ["8", "9"] {
+            assert!(matches!(
+                source.download(&artifact("42"), Some(build_id.into())),
+                Err(ErdError::NotFound { .. })
+            ));
+        }
+        assert!(matches!(
+            source.download(&artifact("42"), None),
+            Err(ErdError::NoSuccessfulJob { .. })
+        ));
+        assert!(matches!(
+            source.download(&artifact("43"), None),
+            Err(ErdError::Unauthorized { .. })
+        ));
+        assert!(matches!(
+            source.download(&artifact("44"), None),
+            Err(ErdError::NoSuccessfulJob { searched: MAX_PIPELINE_PAGES, .. })
+        ));
+    }
+}
@@ -0,0 +1,273 @@
+use log::debug;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+
+use crate::archive::{detect_format, find_artifact_in_archive};
+use crate::config::artifacts::{ArtifactConfig, SourceType};
+use crate::download::Resumable;
+use crate::logins::Login;
+use crate::pattern::ArtifactMatcher;
+use crate::{ArtifactFiles, ErdError, FileData};
+
+use super::{ArtifactSource, BuildInfo, Capabilities};
+use super::retry::{Retry, SendRetrying};
+
+const BUILD_ID_PLACEHOLDER: &str = "{build_id}";
+
+/// Fill in the placeholders of a URL template for the given artifact
+pub fn expand_template(
+    template: &str,
+    base: &str,
+    artifact: &ArtifactConfig,
+    build_id: Option<&str>,
+) -> String {
+    let url = template
+        .replace("{base}", base.trim_end_matches('/'))
+        .replace("{project_id}", &artifact.project_id)
+        .replace("{branch}", &artifact.branch);
+    match build_id {
+        Some(b_id) => url.replace(BUILD_ID_PLACEHOLDER, b_id),
+        None => url,
+    }
+}
+
+/// Parse an index of build ids, either a JSON array or one id per line
+fn parse_index(index: &str) -> Vec<String> {
+    if let Ok(ids) = serde_json::from_str::<Vec<serde_json::Value>>(index) {
+        return ids
+            .into_iter()
+            .map(|id| match id {
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            })
+            .collect();
+    }
+    index
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Files served from plain URLs, such as nightly build servers and static file hosts
+pub struct HttpSource {
+    url: String,
+    login: Option<Login>,
+    client: Client,
+    retry: Retry,
+}
+
+impl HttpSource {
+    pub fn new(url: String, login: Option<Login>, client: Client, retry: Retry) -> Self {
+        Self { url, login, client, retry }
+    }
+
+    fn check_status(&self, response: Response, what: &str) -> Result<Response, ErdError> {
+        super::check_status(SourceType::Http, &self.url, response, what)
+    }
+
+    fn get(&self, client: &Client, url: &str) -> RequestBuilder {
+        let request = client.get(url);
+        match &self.login {
+            Some(login) => request.basic_auth(&login.username, Some(&login.password)),
+            None => request,
+        }
+    }
+
+    fn get_url_template<'a>(&self, artifact: &'a ArtifactConfig) -> Result<&'a str, ErdError> {
+        artifact
+            .url_template
+            .as_deref()
+            .ok_or_else(|| ErdError::InvalidArtifactConfig {
+                artifact: artifact.id.clone(),
+                desc: "url_template must be set for artifacts from HTTP sources".to_string(),
+            })
+    }
+
+    fn get_index(&self, client: &Client, artifact: &ArtifactConfig) -> Result<Vec<String>, ErdError> {
+        let index_template = artifact
+            .index_url
+            .as_deref()
+            .ok_or_else(|| ErdError::InvalidArtifactConfig {
+                artifact: artifact.id.clone(),
+                desc: "index_url must be set to list the available builds".to_string(),
+            })?;
+        let url = expand_template(index_template, &self.url, artifact, None);
+        let response = self
+            .get(client, &url)
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get build index"))
+            .and_then(|r| self.check_status(r, "Received Error while getting build index"))?;
+        let index = response
+            .text()
+            .map_err(|e| request_failed(e, "Failed to read build index"))?;
+        Ok(parse_index(&index))
+    }
+}
+
+impl ArtifactSource for HttpSource {
+    fn kind(&self) -> SourceType {
+        SourceType::Http
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            scan: false,
+            history: true,
+            rebuild: false,
+        }
+    }
+
+    fn latest_build_id(&self, artifact: &ArtifactConfig) -> Result<Option<String>, ErdError> {
+        // Without a build id in the URL, the same URL gives whatever the latest build is
+        match self.get_url_template(artifact)?.contains(BUILD_ID_PLACEHOLDER) {
+            true => Ok(self.get_index(&self.client, artifact)?.pop()),
+            false => Ok(None),
+        }
+    }
+
+    fn download(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<String>,
+    ) -> Result<Option<ArtifactFiles>, ErdError> {
+        let client = &self.client;
+        let template = self.get_url_template(artifact)?;
+        let takes_build_id = template.contains(BUILD_ID_PLACEHOLDER);
+        let build_id = match build_id {
+            Some(_) if !takes_build_id => {
+                return Err(ErdError::InvalidArtifactConfig {
+                    artifact: artifact.id.clone(),
+                    desc: format!("url_template has no {} to fetch a specific build", BUILD_ID_PLACEHOLDER),
+                });
+            }
+            None if takes_build_id => match self.get_index(client, artifact)?.pop() {
+                Some(latest) => Some(latest),
+                None => return Ok(None),
+            },
+            b_id => b_id,
+        };
+        let url = expand_template(template, &self.url, artifact, build_id.as_deref());
+        debug!("Downloading {}", url);
+
+        let (request, resumable) = Resumable::prepare(self.get(client, &url));
+        let response = request
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to download artifact"))
+            .and_then(|r| self.check_status(r, "Received Error while downloading artifact"))?;
+        let file_name = response
+            .url()
+            .path_segments()
+            .and_then(|mut s| s.next_back())
+            .unwrap_or_default()
+            .to_string();
+        let download = resumable.finish(response, "Failed to read data from artifact")?;
+        debug!("{} bytes read", download.len());
+
+        // The download may be the artifact itself (which could be an archive, such as a jar),
+        // or an archive to look for the artifact in
+        if ArtifactMatcher::new(artifact)?.matches(&file_name) && artifact.inner_archive.is_none() {
+            return Ok(Some(FileData {
+                file_name: file_name.into(),
+                data: download,
+                mode: None,
+            }
+            .into()));
+        }
+        match detect_format(&download.header()?) {
+            Some(_) => find_artifact_in_archive(download, artifact),
+            None => Ok(None),
+        }
+    }
+
+    fn history(&self, artifact: &ArtifactConfig) -> Result<Vec<BuildInfo>, ErdError> {
+        let client = &self.client;
+        let template = self.get_url_template(artifact)?;
+        let builds = self
+            .get_index(client, artifact)?
+            .into_iter()
+            .rev()
+            .take(6)
+            .map(|build_id| BuildInfo {
+                web_url: expand_template(template, &self.url, artifact, Some(&build_id)),
+                id: build_id,
+                job_ref: artifact.branch.clone(),
+                timestamp: String::new(),
+                status: "available".to_string(),
+                has_artifacts: true,
+                commit_short_id: String::new(),
+                commit_title: String::new(),
+                commit_author: String::new(),
+                notes: String::new(),
+            })
+            .collect();
+        Ok(builds)
+    }
+}
+
+fn request_failed(error: reqwest::Error, what: &str) -> ErdError {
+    super::request_failed(SourceType::Http, error, what)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sources::test_server::{assert_downloads, serve, test_artifact, zip_of, Route};
+
+    /// Basic auth for the user `ci` with the password `secret`
+    const AUTHORIZATION_HEADER: &str = "Authorization: Basic Y2k6c2VjcmV0";
+
+    fn login(url: &str) -> Login {
+        Login {
+            url: url.to_string(),
+            username: "ci".into(),
+            password: "secret".into(),
+        }
+    }
+
+    #[test]
+    fn test_download_latest_from_index() {
+        let zip = zip_of(&[("plugin-2.jar", b"new build")]);
+        let url = serve(vec![
+            Route::new("/nightly/owner/plugin/main/index.json", r#"[41, "42"]"#).requiring_header(AUTHORIZATION_HEADER),
+            Route::new("/nightly/owner/plugin/main/42/dist.zip", zip).requiring_header(AUTHORIZATION_HEADER),
+        ]);
+        let source = HttpSource::new(format!("{}/nightly/", url), Some(login(&url)), Client::new(), Retry::default());
+        let artifact = ArtifactConfig {
+            url_template: Some("{base}/{project_id}/{branch}/{build_id}/dist.zip".into()),
+            index_url: Some("{base}/{project_id}/{branch}/index.json".into()),
+            ..test_artifact(".jar")
+        };
+        assert_downloads(&source, &artifact, None, "plugin-2.jar", b"new build");
+
+        let history = source.history(&artifact).unwrap();
+        let ids: Vec<&str> = history.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, ["42", "41"]);
+    }
+
+    #[test]
+    fn test_download_errors() {
+        let url = serve(vec![
+            Route::new("/nightly/owner/plugin/main/latest.jar", "jar").requiring_header(AUTHORIZATION_HEADER),
+        ]);
+        let artifact = ArtifactConfig {
+            url_template: Some("{base}/nightly/{project_id}/{branch}/latest.jar".into()),
+            ..test_artifact(".jar")
+        };
+
+        let anonymous = HttpSource::new(url.clone(), None, Client::new(), Retry::default());
+        assert!(matches!(anonymous.download(&artifact, None), Err(ErdError::Unauthorized { .. })));
+
+        let source = HttpSource::new(url.clone(), Some(login(&url)), Client::new(), Retry::default());
+        assert_downloads(&source, &artifact, None, "latest.jar", b"jar");
+        // Without a {build_id} in the template, only the latest build can be fetched
+        assert!(matches!(
+            source.download(&artifact, Some("41".into())),
+            Err(ErdError::InvalidArtifactConfig { .. })
+        ));
+        assert!(matches!(
+            source.download(&test_artifact(".jar"), None),
+            Err(ErdError::InvalidArtifactConfig { .. })
+        ));
+    }
+}
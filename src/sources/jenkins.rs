@@ -0,0 +1,505 @@
+use log::{debug, info, warn};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::LOCATION;
+use reqwest::Url;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::config::artifacts::{ArtifactConfig, SourceType};
+use crate::download::{Download, Resumable};
+use crate::logins::Login;
+use crate::output::ScannedProject;
+use crate::pattern::ArtifactMatcher;
+use crate::{format_timestamp, ArtifactFiles, ErdError, FileData};
+
+use super::{ArtifactSource, BuildInfo, Capabilities};
+use super::retry::{Retry, SendRetrying};
+
+/// The fields requested when describing a single build
+const BUILD_TREE: &str = "number,result,building,timestamp,url,\
+    artifacts[fileName,relativePath],\
+    changeSets[items[commitId,msg,authorEmail,author[fullName]]]";
+
+#[derive(Deserialize)]
+pub struct JobList {
+    #[serde(default)]
+    pub jobs: Vec<JobData>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobData {
+    pub full_name: String,
+    pub url: String,
+    /// Jobs nested inside folders and multibranch projects
+    #[serde(default)]
+    pub jobs: Vec<JobData>,
+}
+
+#[derive(Deserialize)]
+pub struct BuildList {
+    #[serde(default)]
+    pub builds: Vec<Build>,
+}
+
+#[derive(Deserialize)]
+pub struct BuildNumber {
+    pub number: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Build {
+    pub number: usize,
+    pub result: Option<String>,
+    #[serde(default)]
+    pub building: bool,
+    /// Milliseconds since the unix epoch
+    pub timestamp: i64,
+    pub url: String,
+    #[serde(default)]
+    pub artifacts: Vec<BuildArtifact>,
+    #[serde(default)]
+    pub change_sets: Vec<ChangeSet>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildArtifact {
+    pub file_name: String,
+    pub relative_path: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeSet {
+    #[serde(default)]
+    pub items: Vec<ChangeSetItem>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeSetItem {
+    pub commit_id: Option<String>,
+    #[serde(default)]
+    pub msg: String,
+    pub author_email: Option<String>,
+    pub author: Option<ChangeSetAuthor>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeSetAuthor {
+    pub full_name: String,
+}
+
+#[derive(Deserialize)]
+pub struct BuildActions {
+    #[serde(default)]
+    pub actions: Vec<BuildAction>,
+}
+
+#[derive(Deserialize)]
+pub struct BuildAction {
+    #[serde(default)]
+    pub parameters: Vec<BuildParameter>,
+}
+
+#[derive(Deserialize)]
+pub struct BuildParameter {
+    pub name: String,
+    #[serde(default)]
+    pub value: serde_json::Value,
+}
+
+impl BuildParameter {
+    fn value_string(&self) -> String {
+        match &self.value {
+            serde_json::Value::String(s) => s.clone(),
+            serde_json::Value::Null => String::new(),
+            other => other.to_string(),
+        }
+    }
+}
+
+/// Get the URL of the job that builds the given artifact.
+/// The project id is a path of (possibly nested) job names, e.g. `plugins/craftbook`,
+/// and a non-empty branch selects the branch job of a multibranch project.
+pub fn get_job_url(source_url: &str, artifact: &ArtifactConfig) -> Result<Url, ErdError> {
+    let mut url = parse_url(source_url)?;
+    {
+        let mut segments = url
+            .path_segments_mut()
+            .map_err(|_| invalid_url(source_url))?;
+        segments.pop_if_empty();
+        for job in artifact.project_id.split('/').filter(|j| !j.is_empty()) {
+            segments.push("job").push(job);
+        }
+        if !artifact.branch.is_empty() {
+            // Multibranch projects name branch jobs with their slashes encoded
+            segments.push("job").push(&artifact.branch.replace('/', "%2F"));
+        }
+    }
+    Ok(url)
+}
+
+/// Append the given path segments to a URL
+fn join_url(url: &Url, path: &[&str]) -> Url {
+    let mut url = url.clone();
+    url.path_segments_mut()
+        .expect("Job URLs can always have segments")
+        .pop_if_empty()
+        .extend(path);
+    url
+}
+
+fn parse_url(url: &str) -> Result<Url, ErdError> {
+    Url::parse(url).map_err(|_| invalid_url(url))
+}
+
+fn invalid_url(url: &str) -> ErdError {
+    ErdError::SourceRequestError {
+        source: SourceType::Jenkins,
+        url: url.to_string(),
+        desc: "Invalid Jenkins URL".to_string(),
+    }
+}
+
+/// Archived build artifacts from Jenkins jobs
+pub struct JenkinsSource {
+    url: String,
+    login: Option<Login>,
+    client: Client,
+    retry: Retry,
+}
+
+impl JenkinsSource {
+    pub fn new(url: String, login: Option<Login>, client: Client, retry: Retry) -> Self {
+        Self { url, login, client, retry }
+    }
+
+    fn check_status(&self, response: Response, what: &str) -> Result<Response, ErdError> {
+        super::check_status(SourceType::Jenkins, &self.url, response, what)
+    }
+
+    fn with_auth(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.login {
+            // Jenkins takes the user's API token as the password
+            Some(login) => request.basic_auth(&login.username, Some(&login.password)),
+            None => request,
+        }
+    }
+
+    fn get_json<T: DeserializeOwned>(
+        &self,
+        client: &Client,
+        url: Url,
+        tree: &str,
+        what: &str,
+    ) -> Result<T, ErdError> {
+        let request = client.get(url).query(&[("tree", tree)]);
+        let response = self
+            .with_auth(request)
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, &format!("Failed to get {}", what)))
+            .and_then(|r| self.check_status(r, &format!("Received Error while getting {}", what)))?;
+        debug!("URL: {}", response.url());
+        deserialize_response(response)
+    }
+
+    fn download_build_artifact(
+        &self,
+        client: &Client,
+        build_url: &Url,
+        build_artifact: &BuildArtifact,
+    ) -> Result<Download, ErdError> {
+        let mut path = vec!["artifact"];
+        path.extend(build_artifact.relative_path.split('/'));
+        let url = join_url(build_url, &path);
+        let (request, resumable) = Resumable::prepare(self.with_auth(client.get(url)));
+        let response = request
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get artifact from Jenkins"))
+            .and_then(|r| self.check_status(r, "Received Error while downloading artifact"))?;
+        let download = resumable.finish(response, "Failed to read data from artifact")?;
+        debug!("{} bytes read", download.len());
+        Ok(download)
+    }
+}
+
+impl ArtifactSource for JenkinsSource {
+    fn kind(&self) -> SourceType {
+        SourceType::Jenkins
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            scan: true,
+            history: true,
+            rebuild: true,
+        }
+    }
+
+    fn scan(&self, query: Option<String>) -> Result<Vec<ScannedProject>, ErdError> {
+        let client = &self.client;
+        let url = join_url(&parse_url(&self.url)?, &["api", "json"]);
+        let job_list: JobList =
+            self.get_json(client, url, "jobs[fullName,url,jobs[fullName,url]]", "job list")?;
+
+        fn flatten(jobs: Vec<JobData>, out: &mut Vec<ScannedProject>) {
+            for mut job in jobs {
+                let nested = std::mem::take(&mut job.jobs);
+                out.push(ScannedProject {
+                    path: job.full_name.clone(),
+                    id: job.full_name,
+                    url: job.url,
+                });
+                flatten(nested, out);
+            }
+        }
+        let mut projects = vec![];
+        flatten(job_list.jobs, &mut projects);
+        if let Some(q) = query {
+            let q = q.to_lowercase();
+            projects.retain(|p| p.path.to_lowercase().contains(&q));
+        }
+        Ok(projects)
+    }
+
+    fn latest_build_id(&self, artifact: &ArtifactConfig) -> Result<Option<String>, ErdError> {
+        let build_url = join_url(&get_job_url(&self.url, artifact)?, &["lastSuccessfulBuild"]);
+        let build: BuildNumber =
+            self.get_json(&self.client, join_url(&build_url, &["api", "json"]), "number", "build")?;
+        Ok(Some(build.number.to_string()))
+    }
+
+    fn download(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<String>,
+    ) -> Result<Option<ArtifactFiles>, ErdError> {
+        let client = &self.client;
+        let job_url = get_job_url(&self.url, artifact)?;
+        let build_ref = build_id.unwrap_or_else(|| "lastSuccessfulBuild".to_string());
+        let build_url = join_url(&job_url, &[&build_ref]);
+        let build: Build =
+            self.get_json(client, join_url(&build_url, &["api", "json"]), BUILD_TREE, "build")?;
+        debug!("Using build {}", build.number);
+
+        let found = ArtifactMatcher::new(artifact)?.select(&build.artifacts, |a| &a.relative_path)?;
+        match found {
+            Some(build_artifact) => {
+                debug!("Found Artifact: {}", build_artifact.relative_path);
+                // Use the build number so that the build cannot change between requests
+                let build_url = join_url(&job_url, &[&build.number.to_string()]);
+                let data = self.download_build_artifact(client, &build_url, build_artifact)?;
+                Ok(Some(FileData {
+                    file_name: build_artifact.file_name.clone().into(),
+                    data,
+                    mode: None,
+                }
+                .into()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn history(&self, artifact: &ArtifactConfig) -> Result<Vec<BuildInfo>, ErdError> {
+        let client = &self.client;
+        let url = join_url(&get_job_url(&self.url, artifact)?, &["api", "json"]);
+        let tree = format!("builds[{}]{{0,6}}", BUILD_TREE);
+        let build_list: BuildList = self.get_json(client, url, &tree, "build history")?;
+        Ok(build_list
+            .builds
+            .into_iter()
+            .map(|b| b.into_build_info(&artifact.branch))
+            .collect())
+    }
+
+    fn rebuild(&self, artifact: &ArtifactConfig, build_id: String) -> Result<(), ErdError> {
+        let client = &self.client;
+        let job_url = get_job_url(&self.url, artifact)?;
+        let build_url = join_url(&job_url, &[&build_id, "api", "json"]);
+        let build_actions: BuildActions = self.get_json(
+            client,
+            build_url,
+            "actions[parameters[name,value]]",
+            "build parameters",
+        )?;
+        let parameters: Vec<(String, String)> = build_actions
+            .actions
+            .iter()
+            .flat_map(|a| &a.parameters)
+            .map(|p| (p.name.clone(), p.value_string()))
+            .collect();
+
+        let request = match parameters.is_empty() {
+            true => client.post(join_url(&job_url, &["build"])),
+            false => {
+                for (name, value) in &parameters {
+                    info!("> {} = {}", name, value);
+                }
+                client
+                    .post(join_url(&job_url, &["buildWithParameters"]))
+                    .form(&parameters)
+            }
+        };
+        let response = self
+            .with_auth(request)
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, &format!("Failed to rebuild build {} on Jenkins", build_id)))
+            .and_then(|r| self.check_status(r, &format!("Jenkins refused to rebuild build {}", build_id)))?;
+        info!("Queued a new build with the parameters of build {}", build_id);
+        match response.headers().get(LOCATION).and_then(|l| l.to_str().ok()) {
+            Some(queue_item) => info!("> Queue item - {}", queue_item),
+            None => warn!("Jenkins did not report the queued build"),
+        }
+        info!("Check the job history to see when the build is complete and its build number");
+        Ok(())
+    }
+}
+
+fn deserialize_response<T: DeserializeOwned>(response: Response) -> Result<T, ErdError> {
+    super::deserialize_response(SourceType::Jenkins, response)
+}
+
+fn request_failed(error: reqwest::Error, what: &str) -> ErdError {
+    super::request_failed(SourceType::Jenkins, error, what)
+}
+
+impl Build {
+    fn into_build_info(self, branch: &str) -> BuildInfo {
+        let status = match (&self.result, self.building) {
+            (_, true) => "running".to_string(),
+            (Some(result), false) => result.to_lowercase(),
+            (None, false) => "unknown".to_string(),
+        };
+        let change = self.change_sets.into_iter().flat_map(|c| c.items).next_back();
+        let (commit_short_id, commit_title, commit_author) = match change {
+            Some(item) => (
+                item.commit_id.unwrap_or_default().chars().take(8).collect(),
+                item.msg,
+                item.author_email
+                    .or(item.author.map(|a| a.full_name))
+                    .unwrap_or_default(),
+            ),
+            None => (String::new(), "(no changes)".to_string(), String::new()),
+        };
+        BuildInfo {
+            id: self.number.to_string(),
+            job_ref: branch.to_string(),
+            timestamp: format_timestamp(self.timestamp / 1000),
+            status,
+            has_artifacts: !self.artifacts.is_empty(),
+            web_url: self.url,
+            commit_short_id,
+            commit_title,
+            commit_author,
+            notes: String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sources::test_server::{assert_downloads, serve, Route};
+
+    /// Basic auth for the user `ci` with the password `secret`
+    const AUTHORIZATION_HEADER: &str = "Authorization: Basic Y2k6c2VjcmV0";
+
+    #[test]
+    fn test_job_url() {
+        let mut artifact = ArtifactConfig {
+            id: "craftbook".into(),
+            project_id: "plugins/craftbook".into(),
+            branch: "feature/sponge".into(),
+            artifact_pattern: ".jar".into(),
+            ..Default::default()
+        };
+        let url = get_job_url("https://ci.example.com/jenkins/", &artifact).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://ci.example.com/jenkins/job/plugins/job/craftbook/job/feature%252Fsponge"
+        );
+
+        artifact.branch = String::new();
+        let url = get_job_url("https://ci.example.com", &artifact).unwrap();
+        assert_eq!(url.as_str(), "https://ci.example.com/job/plugins/job/craftbook");
+    }
+
+    fn artifact() -> ArtifactConfig {
+        ArtifactConfig {
+            id: "craftbook".into(),
+            project_id: "plugins/craftbook".into(),
+            artifact_pattern: "*.jar".into(),
+            exclude: vec!["*-sources.jar".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_download_latest() {
+        let build = r#"{"number": 12, "result": "SUCCESS", "timestamp": 0, "url": "",
+            "artifacts": [
+                {"fileName": "craftbook-1.0-sources.jar", "relativePath": "build/libs/craftbook-1.0-sources.jar"},
+                {"fileName": "craftbook-1.0.jar", "relativePath": "build/libs/craftbook-1.0.jar"}
+            ]}"#;
+        // Requests are authenticated as "ci" with an API token
+        let url = serve(vec![
+            Route::new("/job/plugins/job/craftbook/lastSuccessfulBuild/api/json", build)
+                .requiring_header(AUTHORIZATION_HEADER),
+            // The artifact is downloaded from the build's number, which can't move on
+            Route::new("/job/plugins/job/craftbook/12/artifact/build/libs/craftbook-1.0.jar", "jar of build 12")
+                .requiring_header(AUTHORIZATION_HEADER),
+        ]);
+
+        let login = Login {
+            url: url.clone(),
+            username: "ci".into(),
+            password: "secret".into(),
+        };
+        let source = JenkinsSource::new(url, Some(login), Client::new(), Retry::default());
+        assert_downloads(&source, &artifact(), None, "craftbook-1.0.jar", b"jar of build 12");
+        assert_eq!(source.latest_build_id(&artifact()).unwrap().as_deref(), Some("12"));
+    }
+
+    #[test]
+    fn test_history() {
+        let builds = r#"{"builds": [
+            {"number": 13, "result": null, "building": true, "timestamp": 0, "url": "https://ci/13"},
+            {"number": 12, "result": "SUCCESS", "timestamp": 0, "url": "https://ci/12",
+             "artifacts": [{"fileName": "craftbook-1.0.jar", "relativePath": "craftbook-1.0.jar"}],
+             "changeSets": [{"items": [{"commitId": "0123456789abcdef", "msg": "Fix things",
+                "author": {"fullName": "A Developer"}}]}]}
+        ]}"#;
+        let url = serve(vec![Route::new("/job/plugins/job/craftbook/api/json", builds)]);
+
+        let source = JenkinsSource::new(url, None, Client::new(), Retry::default());
+        let history = source.history(&artifact()).unwrap();
+        let ids: Vec<&str> = history.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, ["13", "12"]);
+        assert!(!history[0].has_artifacts);
+        assert!(history[1].has_artifacts);
+        assert_eq!(history[1].commit_title, "Fix things");
+    }
+
+    #[test]
+    fn test_rebuild_with_parameters() {
+        let actions = r#"{"actions": [{}, {"parameters": [{"name": "TARGET", "value": "release"}]}]}"#;
+        let url = serve(vec![
+            Route::new("/job/plugins/job/craftbook/12/api/json", actions),
+            Route::new("/job/plugins/job/craftbook/13/api/json", r#"{"actions": []}"#),
+            // A build without parameters would be queued through /build, which isn't served
+            Route::new("/job/plugins/job/craftbook/buildWithParameters", "")
+                .with_status("201 Created")
+                .with_header("Location: https://ci.example.com/queue/item/5/"),
+        ]);
+
+        let source = JenkinsSource::new(url, None, Client::new(), Retry::default());
+        source.rebuild(&artifact(), "12".into()).expect("Failed to rebuild");
+        assert!(matches!(
+            source.rebuild(&artifact(), "13".into()),
+            Err(ErdError::NotFound { .. })
+        ));
+    }
+}
@@ -0,0 +1,233 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::debug;
+
+use crate::config::artifacts::{ArtifactConfig, SourceType};
+use crate::download::Download;
+use crate::output::ScannedProject;
+use crate::pattern::ArtifactMatcher;
+use crate::{format_timestamp, ArtifactFiles, ErdError, FileData};
+
+use super::{ArtifactSource, BuildInfo, Capabilities};
+
+/// A directory containing builds laid out as `<project>/<branch>/<build-id>/`,
+/// such as a local build output folder or a network mount
+pub struct LocalSource {
+    root: PathBuf,
+}
+
+impl LocalSource {
+    pub fn new(url: &str) -> Self {
+        let root = url.strip_prefix("file://").unwrap_or(url);
+        Self { root: root.into() }
+    }
+
+    fn branch_dir(&self, artifact: &ArtifactConfig) -> PathBuf {
+        self.root.join(&artifact.project_id).join(&artifact.branch)
+    }
+
+    /// List the build directories of the artifact, most recently modified first
+    fn list_builds(&self, artifact: &ArtifactConfig) -> Result<Vec<(String, SystemTime)>, ErdError> {
+        let mut builds = list_dirs(&self.branch_dir(artifact))?;
+        builds.sort_by(|(_, a), (_, b)| b.cmp(a));
+        Ok(builds)
+    }
+}
+
+/// List the sub-directories of a directory along with their modification times
+fn list_dirs(dir: &Path) -> Result<Vec<(String, SystemTime)>, ErdError> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| ErdError::IOError(e, format!("Failed to read directory {:?}", dir)))?;
+    let mut dirs = vec![];
+    for entry in entries {
+        let entry = entry
+            .map_err(|e| ErdError::IOError(e, format!("Failed to read directory {:?}", dir)))?;
+        let metadata = entry
+            .metadata()
+            .map_err(|e| ErdError::IOError(e, format!("Failed to read {:?}", entry.path())))?;
+        if !metadata.is_dir() {
+            continue;
+        }
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        dirs.push((entry.file_name().to_string_lossy().to_string(), modified));
+    }
+    Ok(dirs)
+}
+
+/// Find the files within a build directory (recursively) that match the artifact's pattern.
+/// Paths are matched and returned relative to the build directory, with `/` as the separator.
+/// Symlinked directories are not followed, so that links can't loop
+fn find_matching_files(build_dir: &Path, dir: &Path, matcher: &ArtifactMatcher) -> Result<Vec<String>, ErdError> {
+    let mut found = vec![];
+    let entries = fs::read_dir(dir)
+        .map_err(|e| ErdError::IOError(e, format!("Failed to read directory {:?}", dir)))?;
+    for entry in entries {
+        let entry = entry
+            .map_err(|e| ErdError::IOError(e, format!("Failed to read directory {:?}", dir)))?;
+        let path = entry.path();
+        let file_type = entry
+            .file_type()
+            .map_err(|e| ErdError::IOError(e, format!("Failed to read {:?}", path)))?;
+        if file_type.is_dir() {
+            found.extend(find_matching_files(build_dir, &path, matcher)?);
+            continue;
+        }
+        let relative = path
+            .strip_prefix(build_dir)
+            .expect("Files are found within the build directory")
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if matcher.matches(&relative) {
+            found.push(relative);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+impl ArtifactSource for LocalSource {
+    fn kind(&self) -> SourceType {
+        SourceType::Local
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            scan: true,
+            history: true,
+            rebuild: false,
+        }
+    }
+
+    fn scan(&self, query: Option<String>) -> Result<Vec<ScannedProject>, ErdError> {
+        let mut projects: Vec<ScannedProject> = list_dirs(&self.root)?
+            .into_iter()
+            .map(|(name, _)| ScannedProject {
+                url: self.root.join(&name).to_string_lossy().to_string(),
+                path: name.clone(),
+                id: name,
+            })
+            .collect();
+        if let Some(q) = query {
+            let q = q.to_lowercase();
+            projects.retain(|p| p.path.to_lowercase().contains(&q));
+        }
+        projects.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(projects)
+    }
+
+    fn download(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<String>,
+    ) -> Result<Option<ArtifactFiles>, ErdError> {
+        let build_id = match build_id {
+            Some(b_id) => b_id,
+            None => match self.list_builds(artifact)?.into_iter().next() {
+                Some((latest, _)) => latest,
+                None => return Ok(None),
+            },
+        };
+        let build_dir = self.branch_dir(artifact).join(&build_id);
+        debug!("Using build directory {:?}", build_dir);
+
+        let matcher = ArtifactMatcher::new(artifact)?;
+        let found = find_matching_files(&build_dir, &build_dir, &matcher)?;
+        match matcher.select(found, |p| p)? {
+            Some(relative) => {
+                debug!("Found Artifact: {}", relative);
+                let path = build_dir.join(&relative);
+                let file = File::open(&path)
+                    .map_err(|e| ErdError::IOError(e, format!("Failed to open {:?}", path)))?;
+                let data = Download::from_reader(file, &format!("Failed to read {:?}", path))?;
+                Ok(Some(FileData {
+                    file_name: path.file_name().expect("Files always have a name").into(),
+                    data,
+                    mode: None,
+                }
+                .into()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn history(&self, artifact: &ArtifactConfig) -> Result<Vec<BuildInfo>, ErdError> {
+        let matcher = ArtifactMatcher::new(artifact)?;
+        let mut history = vec![];
+        for (build_id, modified) in self.list_builds(artifact)?.into_iter().take(6) {
+            let build_dir = self.branch_dir(artifact).join(&build_id);
+            let has_artifacts = !find_matching_files(&build_dir, &build_dir, &matcher)?.is_empty();
+            let modified_secs = modified
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+            history.push(BuildInfo {
+                id: build_id,
+                job_ref: artifact.branch.clone(),
+                timestamp: format_timestamp(modified_secs),
+                status: "available".to_string(),
+                has_artifacts,
+                web_url: build_dir.to_string_lossy().to_string(),
+                commit_short_id: String::new(),
+                commit_title: String::new(),
+                commit_author: String::new(),
+                notes: String::new(),
+            });
+        }
+        Ok(history)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::sources::test_server::{assert_downloads, test_artifact};
+
+    #[test]
+    fn test_latest_build_by_mtime() {
+        let root = tempfile::tempdir().unwrap();
+        let branch_dir = root.path().join("plugin").join("master");
+        for (build_id, age) in [("build-b", 60), ("build-a", 0)] {
+            let build_dir = branch_dir.join(build_id).join("libs");
+            fs::create_dir_all(&build_dir).unwrap();
+            fs::write(build_dir.join("plugin.jar"), build_id).unwrap();
+            fs::write(build_dir.join("plugin.txt"), "not the artifact").unwrap();
+            let modified = SystemTime::now() - Duration::from_secs(age);
+            File::open(branch_dir.join(build_id))
+                .and_then(|dir| dir.set_modified(modified))
+                .unwrap();
+        }
+
+        let source = LocalSource::new(&format!("file://{}", root.path().display()));
+        let artifact = ArtifactConfig {
+            project_id: "plugin".into(),
+            branch: "master".into(),
+            ..test_artifact(".jar")
+        };
+        assert_downloads(&source, &artifact, None, "plugin.jar", b"build-a");
+        assert_downloads(&source, &artifact, Some("build-b"), "plugin.jar", b"build-b");
+
+        let history = source.history(&artifact).unwrap();
+        let ids: Vec<&str> = history.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, ["build-a", "build-b"]);
+
+        // Patterns are matched against the path within the build
+        let anchored = ArtifactConfig {
+            artifact_pattern: "/^libs/[^/]+\\.jar$/".into(),
+            ..artifact.clone()
+        };
+        assert_downloads(&source, &anchored, None, "plugin.jar", b"build-a");
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(branch_dir.join("build-a"), branch_dir.join("build-a").join("libs").join("loop"))
+                .unwrap();
+            assert_downloads(&source, &anchored, None, "plugin.jar", b"build-a");
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use std::io::{self, Read};
+
+use log::{debug, warn};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::Digest;
+
+use crate::config::artifacts::{ArtifactConfig, SourceType};
+use crate::download::{Download, Resumable};
+use crate::logins::Login;
+use crate::{ArtifactFiles, ErdError, FileData};
+
+use super::{ArtifactSource, BuildInfo, Capabilities};
+use super::retry::{Retry, SendRetrying};
+
+const METADATA_FILE: &str = "maven-metadata.xml";
+const SNAPSHOT_SUFFIX: &str = "-SNAPSHOT";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+    #[serde(default)]
+    pub versioning: Versioning,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Versioning {
+    pub latest: Option<String>,
+    pub release: Option<String>,
+    #[serde(default)]
+    pub versions: Versions,
+    pub snapshot: Option<Snapshot>,
+    #[serde(default)]
+    pub snapshot_versions: SnapshotVersions,
+}
+
+#[derive(Deserialize, Default)]
+pub struct Versions {
+    #[serde(default)]
+    pub version: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub timestamp: Option<String>,
+    pub build_number: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotVersions {
+    #[serde(default)]
+    pub snapshot_version: Vec<SnapshotVersion>,
+}
+
+#[derive(Deserialize)]
+pub struct SnapshotVersion {
+    #[serde(default)]
+    pub classifier: String,
+    pub extension: String,
+    pub value: String,
+}
+
+/// The coordinates of an artifact, from a project id of the form `groupId:artifactId`
+struct Coordinates<'a> {
+    group_id: &'a str,
+    artifact_id: &'a str,
+}
+
+impl<'a> Coordinates<'a> {
+    fn parse(artifact: &'a ArtifactConfig) -> Result<Self, ErdError> {
+        match artifact.project_id.split_once(':') {
+            Some((group_id, artifact_id)) if !group_id.is_empty() && !artifact_id.is_empty() => {
+                Ok(Self { group_id, artifact_id })
+            }
+            _ => Err(ErdError::InvalidArtifactConfig {
+                artifact: artifact.id.clone(),
+                desc: "project_id must be of the form groupId:artifactId for Maven sources".to_string(),
+            }),
+        }
+    }
+
+    /// The path of the artifact's directory within the repository
+    fn path(&self) -> String {
+        format!("{}/{}", self.group_id.replace('.', "/"), self.artifact_id)
+    }
+}
+
+/// The part of the file name after `<artifactId>-<version>`, such as `.jar` or `-all.jar`.
+/// Maven repositories have no standard way to list files, so the file is found by name
+/// and the pattern can't be a glob or regex
+fn get_file_suffix(artifact: &ArtifactConfig) -> Result<&str, ErdError> {
+    let invalid = |desc: &str| ErdError::InvalidArtifactConfig {
+        artifact: artifact.id.clone(),
+        desc: desc.to_string(),
+    };
+    if !artifact.include.is_empty() || !artifact.exclude.is_empty() {
+        return Err(invalid("include and exclude are not supported by Maven sources"));
+    }
+    match artifact.artifact_pattern.as_str() {
+        "" => Ok(".jar"),
+        pattern if pattern.starts_with('/') || pattern.contains(['*', '?', '[', '{']) => Err(invalid(
+            "artifact_pattern must be the classifier and extension of the file for Maven sources, e.g. .jar or -all.jar",
+        )),
+        pattern => Ok(pattern),
+    }
+}
+
+/// Find the timestamped version of a SNAPSHOT that was deployed with the given file suffix
+fn resolve_snapshot(metadata: &Metadata, version: &str, suffix: &str) -> String {
+    let versioning = &metadata.versioning;
+    let found = versioning.snapshot_versions.snapshot_version.iter().find(|v| {
+        let classifier = match v.classifier.as_str() {
+            "" => String::new(),
+            classifier => format!("-{}", classifier),
+        };
+        format!("{}.{}", classifier, v.extension) == suffix
+    });
+    if let Some(snapshot_version) = found {
+        return snapshot_version.value.clone();
+    }
+    // Repositories written by older versions of Maven only have the latest timestamp
+    match &versioning.snapshot {
+        Some(Snapshot {
+            timestamp: Some(timestamp),
+            build_number: Some(build_number),
+        }) => format!(
+            "{}-{}-{}",
+            version.trim_end_matches(SNAPSHOT_SUFFIX),
+            timestamp,
+            build_number
+        ),
+        _ => version.to_string(),
+    }
+}
+
+/// Pick the version that the artifact's branch refers to
+fn select_version(metadata: &Metadata, branch: &str) -> Option<String> {
+    let versioning = &metadata.versioning;
+    let versions = &versioning.versions.version;
+    match branch {
+        "" | "latest" => versioning
+            .latest
+            .clone()
+            .or_else(|| versions.last().cloned()),
+        "release" => versioning.release.clone().or_else(|| {
+            versions
+                .iter()
+                .rfind(|v| !v.ends_with(SNAPSHOT_SUFFIX))
+                .cloned()
+        }),
+        version => Some(version.to_string()),
+    }
+}
+
+/// Jars and other files published to a Maven repository, such as Maven Central, Nexus or Reposilite
+pub struct MavenSource {
+    url: String,
+    login: Option<Login>,
+    client: Client,
+    retry: Retry,
+}
+
+impl MavenSource {
+    pub fn new(url: String, login: Option<Login>, client: Client, retry: Retry) -> Self {
+        Self { url, login, client, retry }
+    }
+
+    fn check_status(&self, response: Response, what: &str) -> Result<Response, ErdError> {
+        super::check_status(SourceType::Maven, &self.url, response, what)
+    }
+
+    fn get(&self, client: &Client, path: &str) -> RequestBuilder {
+        let url = format!("{}/{}", self.url.trim_end_matches('/'), path);
+        let request = client.get(url);
+        match &self.login {
+            Some(login) => request.basic_auth(&login.username, Some(&login.password)),
+            None => request,
+        }
+    }
+
+    /// Send a request for a file in the repository, returning None if it does not exist
+    fn get_file_response(&self, request: RequestBuilder, path: &str) -> Result<Option<Response>, ErdError> {
+        debug!("Downloading {}", path);
+        let response = request
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get file"))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        self.check_status(response, "Received Error while getting file").map(Some)
+    }
+
+    /// Get a small file from the repository, such as metadata or a checksum
+    fn get_file(&self, client: &Client, path: &str) -> Result<Option<Vec<u8>>, ErdError> {
+        let Some(mut response) = self.get_file_response(self.get(client, path), path)? else {
+            return Ok(None);
+        };
+        let mut buffer = vec![];
+        let bytes_read = response
+            .read_to_end(&mut buffer)
+            .map_err(|e| ErdError::IOError(e, format!("Failed to read data from {}", path)))?;
+        debug!("{} bytes read", bytes_read);
+        Ok(Some(buffer))
+    }
+
+    /// Download a file from the repository, or None if it does not exist
+    fn download_file(&self, client: &Client, path: &str) -> Result<Option<Download>, ErdError> {
+        let (request, resumable) = Resumable::prepare(self.get(client, path));
+        let Some(response) = self.get_file_response(request, path)? else {
+            return Ok(None);
+        };
+        let download = resumable.finish(response, &format!("Failed to read data from {}", path))?;
+        debug!("{} bytes read", download.len());
+        Ok(Some(download))
+    }
+
+    fn get_metadata<T: DeserializeOwned>(&self, client: &Client, dir: &str) -> Result<Option<T>, ErdError> {
+        let path = format!("{}/{}", dir, METADATA_FILE);
+        let Some(buffer) = self.get_file(client, &path)? else {
+            return Ok(None);
+        };
+        let metadata = quick_xml::de::from_reader(buffer.as_slice()).map_err(|e| {
+            ErdError::SourceRequestError {
+                source: SourceType::Maven,
+                url: path,
+                desc: format!("Failed to deserialize metadata: {}", e),
+            }
+        })?;
+        Ok(Some(metadata))
+    }
+
+    /// Check the downloaded file against the checksums published alongside it
+    fn verify_checksum(&self, client: &Client, path: &str, download: &Download) -> Result<(), ErdError> {
+        let actual_sha256 = super::to_hex(download.sha256());
+        let mut sha1 = Sha1::new();
+        io::copy(&mut download.open()?, &mut sha1)
+            .map_err(|e| ErdError::IOError(e, format!("Failed to read downloaded {}", path)))?;
+        let actual_sha1 = format!("{:x}", sha1.finalize());
+        for (extension, actual) in [("sha256", actual_sha256), ("sha1", actual_sha1)] {
+            let checksum_path = format!("{}.{}", path, extension);
+            let Some(checksum) = self.get_file(client, &checksum_path)? else {
+                continue;
+            };
+            // Some tools write the file name after the checksum
+            let expected = String::from_utf8_lossy(&checksum)
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_lowercase();
+            if expected != actual {
+                return Err(ErdError::ChecksumMismatch {
+                    file: path.to_string(),
+                    expected,
+                    actual,
+                });
+            }
+            debug!("Verified {} of {}", extension, path);
+            return Ok(());
+        }
+        warn!("No checksum was published for {}, unable to verify it", path);
+        Ok(())
+    }
+}
+
+impl ArtifactSource for MavenSource {
+    fn kind(&self) -> SourceType {
+        SourceType::Maven
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            // There is no standard API for searching a Maven repository
+            scan: false,
+            history: true,
+            rebuild: false,
+        }
+    }
+
+    fn latest_build_id(&self, artifact: &ArtifactConfig) -> Result<Option<String>, ErdError> {
+        let artifact_dir = Coordinates::parse(artifact)?.path();
+        let metadata: Option<Metadata> = self.get_metadata(&self.client, &artifact_dir)?;
+        // A SNAPSHOT version is deployed again and again under the same name
+        Ok(metadata
+            .and_then(|m| select_version(&m, &artifact.branch))
+            .filter(|v| !v.ends_with(SNAPSHOT_SUFFIX)))
+    }
+
+    fn download(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<String>,
+    ) -> Result<Option<ArtifactFiles>, ErdError> {
+        let client = &self.client;
+        let coordinates = Coordinates::parse(artifact)?;
+        let suffix = get_file_suffix(artifact)?;
+        let artifact_dir = coordinates.path();
+        let version = match build_id {
+            Some(b_id) => b_id,
+            None => {
+                let metadata: Option<Metadata> = self.get_metadata(client, &artifact_dir)?;
+                match metadata.and_then(|m| select_version(&m, &artifact.branch)) {
+                    Some(version) => version,
+                    None => {
+                        warn!("No versions of {} have been published", artifact.project_id);
+                        return Ok(None);
+                    }
+                }
+            }
+        };
+        debug!("Using version {}", version);
+
+        let version_dir = format!("{}/{}", artifact_dir, version);
+        let file_version = match version.ends_with(SNAPSHOT_SUFFIX) {
+            true => match self.get_metadata(client, &version_dir)? {
+                Some(metadata) => resolve_snapshot(&metadata, &version, suffix),
+                None => version.clone(),
+            },
+            false => version.clone(),
+        };
+        let file_name = format!("{}-{}{}", coordinates.artifact_id, file_version, suffix);
+        let path = format!("{}/{}", version_dir, file_name);
+
+        let download = match self.download_file(client, &path)? {
+            Some(download) => download,
+            None => return Ok(None),
+        };
+        self.verify_checksum(client, &path, &download)?;
+        Ok(Some(FileData {
+            file_name: file_name.into(),
+            data: download,
+            mode: None,
+        }
+        .into()))
+    }
+
+    fn history(&self, artifact: &ArtifactConfig) -> Result<Vec<BuildInfo>, ErdError> {
+        let client = &self.client;
+        let artifact_dir = Coordinates::parse(artifact)?.path();
+        let metadata: Metadata = match self.get_metadata(client, &artifact_dir)? {
+            Some(metadata) => metadata,
+            None => return Ok(vec![]),
+        };
+        let builds = metadata
+            .versioning
+            .versions
+            .version
+            .into_iter()
+            .rev()
+            .take(6)
+            .map(|version| BuildInfo {
+                web_url: format!("{}/{}/{}/", self.url.trim_end_matches('/'), artifact_dir, version),
+                id: version,
+                job_ref: artifact.branch.clone(),
+                timestamp: String::new(),
+                status: "available".to_string(),
+                has_artifacts: true,
+                commit_short_id: String::new(),
+                commit_title: String::new(),
+                commit_author: String::new(),
+                notes: String::new(),
+            })
+            .collect();
+        Ok(builds)
+    }
+}
+
+fn request_failed(error: reqwest::Error, what: &str) -> ErdError {
+    super::request_failed(SourceType::Maven, error, what)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sources::test_server::{assert_downloads, serve, test_artifact, Route};
+
+    #[test]
+    fn test_download_snapshot() {
+        let metadata = r#"<metadata>
+  <groupId>com.example</groupId>
+  <artifactId>plugin</artifactId>
+  <versioning>
+    <latest>1.1-SNAPSHOT</latest>
+    <release>1.0</release>
+    <versions><version>1.0</version><version>1.1-SNAPSHOT</version></versions>
+  </versioning>
+</metadata>"#;
+        let snapshot_metadata = r#"<metadata>
+  <versioning>
+    <snapshot><timestamp>20240102.030405</timestamp><buildNumber>7</buildNumber></snapshot>
+    <snapshotVersions>
+      <snapshotVersion><extension>pom</extension><value>1.1-20240102.030405-7</value></snapshotVersion>
+      <snapshotVersion><classifier>all</classifier><extension>jar</extension><value>1.1-20240102.030405-6</value></snapshotVersion>
+    </snapshotVersions>
+  </versioning>
+</metadata>"#;
+        let jar = b"snapshot jar";
+        let jar_path = "/maven/com/example/plugin/1.1-SNAPSHOT/plugin-1.1-20240102.030405-6-all.jar";
+        let url = serve(vec![
+            Route::new("/maven/com/example/plugin/maven-metadata.xml", metadata),
+            Route::new("/maven/com/example/plugin/1.1-SNAPSHOT/maven-metadata.xml", snapshot_metadata),
+            Route::new(jar_path, &jar[..]),
+            Route::new(&format!("{}.sha1", jar_path), format!("{:x}", Sha1::digest(jar))),
+            // A release whose published checksum doesn't match the jar
+            Route::new("/maven/com/example/plugin/1.0/plugin-1.0-all.jar", "release jar"),
+            Route::new("/maven/com/example/plugin/1.0/plugin-1.0-all.jar.sha1", format!("{:x}", Sha1::digest(jar))),
+        ]);
+
+        let source = MavenSource::new(format!("{}/maven/", url), None, Client::new(), Retry::default());
+        let artifact = ArtifactConfig {
+            project_id: "com.example:plugin".into(),
+            branch: "latest".into(),
+            ..test_artifact("-all.jar")
+        };
+        assert_downloads(&source, &artifact, None, "plugin-1.1-20240102.030405-6-all.jar", jar);
+        assert!(matches!(
+            source.download(&artifact, Some("1.0".into())),
+            Err(ErdError::ChecksumMismatch { .. })
+        ));
+
+        let history = source.history(&artifact).unwrap();
+        let ids: Vec<&str> = history.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, ["1.1-SNAPSHOT", "1.0"]);
+
+        for artifact_pattern in ["*-all.jar", "/-all\\.jar$/"] {
+            let artifact = ArtifactConfig {
+                artifact_pattern: artifact_pattern.into(),
+                ..artifact.clone()
+            };
+            assert!(matches!(
+                source.download(&artifact, None),
+                Err(ErdError::InvalidArtifactConfig { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+
+use reqwest::blocking::Response;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+
+use crate::config::artifacts::{ArtifactConfig, SourceConfig, SourceType};
+use crate::logins::Login;
+use crate::output::ScannedProject;
+use crate::{ArtifactFiles, ErdError};
+
+pub mod client;
+pub mod command;
+pub mod gitea;
+pub mod github;
+pub mod gitlab;
+pub mod http;
+pub mod jenkins;
+pub mod local;
+pub mod maven;
+pub mod oci;
+pub mod retry;
+pub mod s3;
+#[cfg(test)]
+pub(crate) mod test_server;
+
+/// An optional operation that a source may or may not support
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Scan,
+    History,
+    Rebuild,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Scan => write!(f, "scan"),
+            Operation::History => write!(f, "history"),
+            Operation::Rebuild => write!(f, "rebuild"),
+        }
+    }
+}
+
+/// The optional operations supported by a source
+#[derive(Debug, Clone, Copy)]
+pub struct Capabilities {
+    pub scan: bool,
+    pub history: bool,
+    pub rebuild: bool,
+}
+
+impl Capabilities {
+    pub fn supports(&self, operation: Operation) -> bool {
+        match operation {
+            Operation::Scan => self.scan,
+            Operation::History => self.history,
+            Operation::Rebuild => self.rebuild,
+        }
+    }
+}
+
+/// A single build of an artifact, as listed by `erd history`
+pub struct BuildInfo {
+    pub id: String,
+    pub job_ref: String,
+    pub timestamp: String,
+    pub status: String,
+    pub has_artifacts: bool,
+    pub web_url: String,
+    pub commit_short_id: String,
+    pub commit_title: String,
+    pub commit_author: String,
+    /// Release notes or a description of the build, if the source has them
+    pub notes: String,
+}
+
+/// The hash of the file that a source would download, as published by the source
+pub struct RemoteHash {
+    pub file_name: PathBuf,
+    pub sha256: Vec<u8>,
+}
+
+/// Somewhere that artifacts can be retrieved from.
+/// Optional operations return [`ErdError::Unsupported`] unless overridden.
+/// Sources are shared between the threads fetching their artifacts.
+pub trait ArtifactSource: Send + Sync {
+    fn kind(&self) -> SourceType;
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// Find projects that artifacts could be added from
+    fn scan(&self, _query: Option<String>) -> Result<Vec<ScannedProject>, ErdError> {
+        Err(self.unsupported(Operation::Scan))
+    }
+
+    /// List the most recent builds of the given artifact
+    fn history(&self, _artifact: &ArtifactConfig) -> Result<Vec<BuildInfo>, ErdError> {
+        Err(self.unsupported(Operation::History))
+    }
+
+    /// Download the given artifact, either the latest or the given build.
+    /// Returns None if the build did not contain a matching file
+    fn download(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<String>,
+    ) -> Result<Option<ArtifactFiles>, ErdError>;
+
+    /// Find the id of the latest build of the artifact, so that it can be fetched from the cache.
+    /// Returns None if the source can't say without extra requests, or if the id could refer to
+    /// different files over time, in which case the latest build is always downloaded
+    fn latest_build_id(&self, _artifact: &ArtifactConfig) -> Result<Option<String>, ErdError> {
+        Ok(None)
+    }
+
+    /// Get the hash of the file that [`download`](Self::download) would return without downloading it.
+    /// Returns None if the source does not publish hashes, in which case the file is always downloaded
+    fn remote_hash(
+        &self,
+        _artifact: &ArtifactConfig,
+        _build_id: Option<&str>,
+    ) -> Result<Option<RemoteHash>, ErdError> {
+        Ok(None)
+    }
+
+    /// Request that the given build is run again
+    fn rebuild(&self, _artifact: &ArtifactConfig, _build_id: String) -> Result<(), ErdError> {
+        Err(self.unsupported(Operation::Rebuild))
+    }
+
+    fn unsupported(&self, operation: Operation) -> ErdError {
+        ErdError::Unsupported {
+            source: self.kind(),
+            operation,
+        }
+    }
+
+    /// Fail with a clean error if the given operation is not supported
+    fn check_supported(&self, operation: Operation) -> Result<(), ErdError> {
+        match self.capabilities().supports(operation) {
+            true => Ok(()),
+            false => Err(self.unsupported(operation)),
+        }
+    }
+}
+
+/// Create the artifact source described by the given configuration
+pub fn create_source(
+    config: &SourceConfig,
+    login: Option<&Login>,
+) -> Result<Box<dyn ArtifactSource>, ErdError> {
+    let token = login.map(|l| l.password.clone());
+    let client = || client::build_client(config);
+    let retry = retry::Retry::new(config.retries.unwrap_or(retry::DEFAULT_RETRIES));
+    Ok(match config.kind {
+        SourceType::Gitlab => Box::new(gitlab::GitlabSource::new(config.url.clone(), token, client()?, retry)),
+        SourceType::Github => Box::new(github::GithubSource::new(config.url.clone(), token, client()?, retry)),
+        SourceType::Gitea => Box::new(gitea::GiteaSource::new(config.url.clone(), token, client()?, retry)),
+        SourceType::Jenkins => Box::new(jenkins::JenkinsSource::new(config.url.clone(), login.cloned(), client()?, retry)),
+        SourceType::Http => Box::new(http::HttpSource::new(config.url.clone(), login.cloned(), client()?, retry)),
+        SourceType::Local => Box::new(local::LocalSource::new(&config.url)),
+        SourceType::Maven => Box::new(maven::MavenSource::new(config.url.clone(), login.cloned(), client()?, retry)),
+        SourceType::Oci => Box::new(oci::OciSource::new(config.url.clone(), login.cloned(), client()?, retry)),
+        SourceType::Command => Box::new(command::CommandSource::new(config, login.cloned())?),
+        SourceType::S3 => Box::new(s3::S3Source::new(
+            config.url.clone(),
+            config.region.clone(),
+            login.cloned(),
+            client()?,
+            retry,
+        )),
+    })
+}
+
+/// Hex encode a hash, in lowercase
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parse a hex encoded hash into its bytes
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Get the token for a source, or fail if the user has not logged in
+fn require_token<'a>(token: &'a Option<String>, source_url: &str) -> Result<&'a str, ErdError> {
+    token.as_deref().ok_or_else(|| ErdError::NoLogin {
+        source_url: source_url.to_string(),
+    })
+}
+
+fn deserialize_response<T: DeserializeOwned>(
+    source: SourceType,
+    response: Response,
+) -> Result<T, ErdError> {
+    let url = response.url().to_string();
+    let response_text = response
+        .text()
+        .map_err(|e| unexpected_response(source, e))?;
+    serde_json::from_str(&response_text).map_err(|e| ErdError::SourceRequestError {
+        source,
+        url: url.clone(),
+        desc: format!("Failed to deserialize response from {:?}: {}", source, e),
+    })
+}
+
+fn unexpected_response(source: SourceType, error: reqwest::Error) -> ErdError {
+    let url = error
+        .url()
+        .map(|url| url.to_string())
+        .unwrap_or_else(|| "UNKNOWN".to_string());
+    ErdError::SourceRequestError {
+        desc: format!("Unexpected response from {:?}: {}", source, error),
+        source,
+        url,
+    }
+}
+
+/// Fail with an error that explains what to do about it if the response is not a success
+fn check_status(source: SourceType, source_url: &str, response: Response, what: &str) -> Result<Response, ErdError> {
+    match response.status() {
+        StatusCode::UNAUTHORIZED => Err(ErdError::Unauthorized {
+            source_url: source_url.to_string(),
+        }),
+        StatusCode::FORBIDDEN => Err(ErdError::Forbidden {
+            source_url: source_url.to_string(),
+            url: response.url().to_string(),
+        }),
+        StatusCode::NOT_FOUND => Err(ErdError::NotFound {
+            source,
+            url: response.url().to_string(),
+        }),
+        _ => response
+            .error_for_status()
+            .map_err(|e| request_failed(source, e, what)),
+    }
+}
+
+fn request_failed(source: SourceType, error: reqwest::Error, what: &str) -> ErdError {
+    let url = error
+        .url()
+        .map(|url| url.to_string())
+        .unwrap_or_else(|| "UNKNOWN".to_string());
+    ErdError::SourceRequestError {
+        source,
+        url,
+        desc: format!("{}: {}", what, error),
+    }
+}
@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use log::{debug, warn};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, LINK, WWW_AUTHENTICATE};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::config::artifacts::{ArtifactConfig, SourceType};
+use crate::download::Resumable;
+use crate::logins::Login;
+use crate::pattern::ArtifactMatcher;
+use crate::{ArtifactFiles, ErdError, FileData};
+
+use super::{ArtifactSource, BuildInfo, Capabilities, RemoteHash};
+use super::retry::{Retry, SendRetrying};
+
+const MANIFEST_TYPES: &str =
+    "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+const CREATED_ANNOTATION: &str = "org.opencontainers.image.created";
+const DIGEST_HEADER: &str = "docker-content-digest";
+
+#[derive(Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub layers: Vec<Descriptor>,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub struct Descriptor {
+    pub digest: String,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub struct TagList {
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TokenResponse {
+    pub token: Option<String>,
+    pub access_token: Option<String>,
+}
+
+/// Parse the parameters of a `WWW-Authenticate: Bearer ...` challenge
+fn parse_bearer_challenge(challenge: &str) -> Option<HashMap<String, String>> {
+    let (scheme, params) = challenge.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    let mut parsed = HashMap::new();
+    let mut rest = params.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_lowercase();
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        parsed.insert(key, value.to_string());
+        rest = remaining.trim_start_matches(',').trim();
+    }
+    Some(parsed)
+}
+
+/// Parse a digest of the form `sha256:<hex>` into its bytes
+fn parse_sha256_digest(digest: &str) -> Option<Vec<u8>> {
+    super::parse_hex(digest.strip_prefix("sha256:")?)
+}
+
+/// Get the URL of the next page from a `Link: <url>; rel="next"` header
+fn get_next_link(headers: &HeaderMap) -> Option<String> {
+    let link = headers.get(LINK)?.to_str().ok()?;
+    let (target, params) = link.split_once(';')?;
+    if !params.contains("rel=\"next\"") {
+        return None;
+    }
+    Some(target.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+}
+
+/// A layer of an OCI artifact that holds the artifact's file
+struct FileLayer {
+    file_name: String,
+    digest: String,
+}
+
+/// Get the layers that are files, along with their titles
+fn get_titled_layers(manifest: &Manifest) -> impl Iterator<Item = (&str, &Descriptor)> {
+    manifest
+        .layers
+        .iter()
+        .filter_map(|layer| Some((layer.annotations.get(TITLE_ANNOTATION)?.as_str(), layer)))
+}
+
+/// Find the layer whose title matches the artifact's pattern
+fn find_file_layer(manifest: &Manifest, matcher: &ArtifactMatcher) -> Result<Option<FileLayer>, ErdError> {
+    let found = matcher.select(get_titled_layers(manifest), |(title, _)| title)?;
+    Ok(found.map(|(title, layer)| FileLayer {
+        file_name: title.to_string(),
+        digest: layer.digest.clone(),
+    }))
+}
+
+/// Files pushed as OCI artifacts (e.g. with ORAS) to a container registry
+pub struct OciSource {
+    url: String,
+    login: Option<Login>,
+    /// The bearer token from the registry's token service, once one is needed
+    token: Mutex<Option<String>>,
+    client: Client,
+    retry: Retry,
+}
+
+impl OciSource {
+    pub fn new(url: String, login: Option<Login>, client: Client, retry: Retry) -> Self {
+        Self {
+            url,
+            login,
+            token: Mutex::new(None),
+            client,
+            retry,
+        }
+    }
+
+    fn check_status(&self, response: Response, what: &str) -> Result<Response, ErdError> {
+        super::check_status(SourceType::Oci, &self.url, response, what)
+    }
+
+    fn get_repository_url(&self, artifact: &ArtifactConfig) -> String {
+        format!("{}/v2/{}", self.url.trim_end_matches('/'), artifact.project_id)
+    }
+
+    /// Get the reference (tag or digest) of the build to use
+    fn get_reference<'a>(artifact: &'a ArtifactConfig, build_id: Option<&'a str>) -> &'a str {
+        match build_id {
+            Some(b_id) => b_id,
+            None if artifact.branch.is_empty() => "latest",
+            None => &artifact.branch,
+        }
+    }
+
+    fn send_once(&self, client: &Client, url: &str, headers: &HeaderMap) -> Result<Response, ErdError> {
+        let mut request = client.get(url).headers(headers.clone());
+        let token = self.token.lock().expect("Token lock was poisoned").clone();
+        request = match (token, &self.login) {
+            (Some(token), _) => request.header(AUTHORIZATION, format!("Bearer {}", token)),
+            (None, Some(login)) => request.basic_auth(&login.username, Some(&login.password)),
+            (None, None) => request,
+        };
+        request
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to send request to registry"))
+    }
+
+    /// Send a request, authenticating with the registry's token service if it asks for a token
+    fn send(&self, client: &Client, url: &str, headers: HeaderMap) -> Result<Response, ErdError> {
+        let response = self.send_once(client, url, &headers)?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+        let challenge = response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|h| h.to_str().ok())
+            .and_then(parse_bearer_challenge);
+        match challenge {
+            Some(challenge) => {
+                let token = self.get_token(client, &challenge)?;
+                *self.token.lock().expect("Token lock was poisoned") = Some(token);
+                self.send_once(client, url, &headers)
+            }
+            None => Ok(response),
+        }
+    }
+
+    fn get_token(&self, client: &Client, challenge: &HashMap<String, String>) -> Result<String, ErdError> {
+        let realm = challenge.get("realm").ok_or_else(|| ErdError::SourceRequestError {
+            source: SourceType::Oci,
+            url: self.url.clone(),
+            desc: "Registry asked for a token without saying where to get one".to_string(),
+        })?;
+        debug!("Requesting token from {}", realm);
+        let query: Vec<(&str, &String)> = ["service", "scope"]
+            .into_iter()
+            .filter_map(|key| challenge.get(key).map(|value| (key, value)))
+            .collect();
+        let mut request = client.get(realm).query(&query);
+        if let Some(login) = &self.login {
+            request = request.basic_auth(&login.username, Some(&login.password));
+        }
+        let response = request
+            .send_retrying(&self.retry)
+            .map_err(|e| request_failed(e, "Failed to get registry token"))
+            .and_then(|r| self.check_status(r, "Received Error while getting registry token"))?;
+        let token: TokenResponse = deserialize_response(response)?;
+        token
+            .token
+            .or(token.access_token)
+            .ok_or_else(|| ErdError::SourceRequestError {
+                source: SourceType::Oci,
+                url: realm.clone(),
+                desc: "Token service did not return a token".to_string(),
+            })
+    }
+
+    /// Get the manifest for the given tag or digest, along with its digest
+    fn get_manifest(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+        reference: &str,
+    ) -> Result<(Manifest, Option<String>), ErdError> {
+        let url = format!("{}/manifests/{}", self.get_repository_url(artifact), reference);
+        let headers = HeaderMap::from_iter([(ACCEPT, HeaderValue::from_static(MANIFEST_TYPES))]);
+        let response = self
+            .send(client, &url, headers)
+            .and_then(|r| self.check_status(r, "Received Error while getting manifest"))?;
+        let digest = response
+            .headers()
+            .get(DIGEST_HEADER)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+        Ok((deserialize_response(response)?, digest))
+    }
+
+    fn get_tags(&self, client: &Client, artifact: &ArtifactConfig) -> Result<Vec<String>, ErdError> {
+        let mut tags = vec![];
+        let mut url = format!("{}/tags/list", self.get_repository_url(artifact));
+        loop {
+            let response = self
+                .send(client, &url, HeaderMap::new())
+                .and_then(|r| self.check_status(r, "Received Error while listing tags"))?;
+            let next = get_next_link(response.headers());
+            let page: TagList = deserialize_response(response)?;
+            tags.extend(page.tags);
+            match next {
+                Some(next) => url = format!("{}{}", self.url.trim_end_matches('/'), next),
+                None => return Ok(tags),
+            }
+        }
+    }
+
+    fn find_layer(
+        &self,
+        client: &Client,
+        artifact: &ArtifactConfig,
+        build_id: Option<&str>,
+    ) -> Result<Option<FileLayer>, ErdError> {
+        let reference = Self::get_reference(artifact, build_id);
+        debug!("Using reference {}", reference);
+        let (manifest, _) = self.get_manifest(client, artifact, reference)?;
+        let layer = find_file_layer(&manifest, &ArtifactMatcher::new(artifact)?)?;
+        if layer.is_none() {
+            warn!("No layer of {}:{} has a title matching the artifact", artifact.project_id, reference);
+        }
+        Ok(layer)
+    }
+}
+
+impl ArtifactSource for OciSource {
+    fn kind(&self) -> SourceType {
+        SourceType::Oci
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            // The catalog API is disabled on most registries
+            scan: false,
+            history: true,
+            rebuild: false,
+        }
+    }
+
+    fn remote_hash(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<&str>,
+    ) -> Result<Option<RemoteHash>, ErdError> {
+        let client = &self.client;
+        Ok(self
+            .find_layer(client, artifact, build_id)?
+            .and_then(|layer| {
+                Some(RemoteHash {
+                    sha256: parse_sha256_digest(&layer.digest)?,
+                    file_name: PathBuf::from(layer.file_name),
+                })
+            }))
+    }
+
+    fn download(
+        &self,
+        artifact: &ArtifactConfig,
+        build_id: Option<String>,
+    ) -> Result<Option<ArtifactFiles>, ErdError> {
+        let client = &self.client;
+        let layer = match self.find_layer(client, artifact, build_id.as_deref())? {
+            Some(layer) => layer,
+            None => return Ok(None),
+        };
+        debug!("Downloading layer {} ({})", layer.file_name, layer.digest);
+        let url = format!("{}/blobs/{}", self.get_repository_url(artifact), layer.digest);
+        let resumable = Resumable::new(&url);
+        let response = self
+            .send(client, &url, resumable.headers())
+            .and_then(|r| self.check_status(r, "Received Error while downloading layer"))?;
+        let download = resumable.finish(response, "Failed to read data from layer")?;
+        debug!("{} bytes read", download.len());
+
+        if let Some(expected) = parse_sha256_digest(&layer.digest) {
+            if download.sha256() != expected.as_slice() {
+                return Err(ErdError::ChecksumMismatch {
+                    file: layer.file_name,
+                    expected: layer.digest,
+                    actual: format!("sha256:{}", super::to_hex(download.sha256())),
+                });
+            }
+        }
+        Ok(Some(FileData {
+            file_name: layer.file_name.into(),
+            data: download,
+            mode: None,
+        }
+        .into()))
+    }
+
+    fn history(&self, artifact: &ArtifactConfig) -> Result<Vec<BuildInfo>, ErdError> {
+        let client = &self.client;
+        let matcher = ArtifactMatcher::new(artifact)?;
+        let mut builds = vec![];
+        for tag in self.get_tags(client, artifact)?.into_iter().rev().take(6) {
+            let (manifest, digest) = self.get_manifest(client, artifact, &tag)?;
+            builds.push(BuildInfo {
+                web_url: format!("{}/manifests/{}", self.get_repository_url(artifact), tag),
+                has_artifacts: get_titled_layers(&manifest).any(|(title, _)| matcher.matches(title)),
+                timestamp: manifest
+                    .annotations
+                    .get(CREATED_ANNOTATION)
+                    .cloned()
+                    .unwrap_or_default(),
+                id: tag,
+                job_ref: artifact.branch.clone(),
+                status: "available".to_string(),
+                commit_short_id: digest
+                    .map(|d| d.trim_start_matches("sha256:").chars().take(12).collect())
+                    .unwrap_or_default(),
+                commit_title: String::new(),
+                commit_author: String::new(),
+                notes: String::new(),
+            });
+        }
+        Ok(builds)
+    }
+}
+
+fn deserialize_response<T: DeserializeOwned>(response: Response) -> Result<T, ErdError> {
+    super::deserialize_response(SourceType::Oci, response)
+}
+
+fn request_failed(error: reqwest::Error, what: &str) -> ErdError {
+    super::request_failed(SourceType::Oci, error, what)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sources::test_server::{assert_downloads, serve, test_artifact, Route};
+    use sha2::{Digest, Sha256};
+
+    const BEARER_HEADER: &str = "Authorization: Bearer registry-token";
+
+    /// A manifest with a README layer and a layer holding the given file
+    fn manifest_of(file_name: &str, digest: &str) -> String {
+        format!(
+            r#"{{
+  "schemaVersion": 2,
+  "mediaType": "application/vnd.oci.image.manifest.v1+json",
+  "artifactType": "application/vnd.example.plugin",
+  "config": {{"mediaType": "application/vnd.oci.empty.v1+json", "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a", "size": 2}},
+  "layers": [
+    {{"mediaType": "text/plain", "digest": "sha256:0000000000000000000000000000000000000000000000000000000000000000", "size": 6, "annotations": {{"org.opencontainers.image.title": "README.md"}}}},
+    {{"mediaType": "application/java-archive", "digest": "{}", "size": 10, "annotations": {{"org.opencontainers.image.title": "{}"}}}}
+  ]
+}}"#,
+            digest, file_name
+        )
+    }
+
+    #[test]
+    fn test_download_with_token() {
+        let jar = b"plugin jar";
+        let digest = format!("sha256:{:x}", Sha256::digest(jar));
+        // The token service checks the login ("ci:secret") before handing out a token
+        let token_url = serve(vec![
+            Route::new("/token", r#"{"token": "registry-token"}"#).requiring_header("Authorization: Basic Y2k6c2VjcmV0"),
+        ]);
+        let url = serve(vec![
+            Route::new("/v2/owner/plugin/manifests/main", "{}")
+                .with_status("401 Unauthorized")
+                .with_header(&format!(
+                    r#"WWW-Authenticate: Bearer realm="{}/token",service="registry",scope="repository:owner/plugin:pull""#,
+                    token_url
+                ))
+                .times(1),
+            Route::new("/v2/owner/plugin/manifests/main", manifest_of("plugin-1.0.jar", &digest))
+                .requiring_header(BEARER_HEADER),
+            Route::new(&format!("/v2/owner/plugin/blobs/{}", digest), &jar[..]).requiring_header(BEARER_HEADER),
+        ]);
+        let login = Login {
+            url: url.clone(),
+            username: "ci".into(),
+            password: "secret".into(),
+        };
+        let source = OciSource::new(url, Some(login), Client::new(), Retry::default());
+        let artifact = test_artifact(".jar");
+        assert_downloads(&source, &artifact, None, "plugin-1.0.jar", jar);
+
+        // The token is kept for later requests
+        let remote_hash = source
+            .remote_hash(&artifact, None)
+            .expect("Failed to get hash")
+            .expect("Layer was not found");
+        assert_eq!(remote_hash.sha256, Sha256::digest(jar).to_vec());
+    }
+
+    #[test]
+    fn test_history_pages() {
+        let digest = format!("sha256:{:x}", Sha256::digest(b"plugin jar"));
+        let url = serve(vec![
+            Route::new("/v2/owner/plugin/tags/list", r#"{"tags": ["v1.0"]}"#)
+                .with_header(r#"Link: </v2/owner/plugin/tags/list?last=v1.0>; rel="next""#)
+                .times(1),
+            Route::new("/v2/owner/plugin/tags/list", r#"{"tags": ["v1.1"]}"#),
+            Route::new("/v2/owner/plugin/manifests/v1.0", manifest_of("plugin-1.0.jar", &digest)),
+            Route::new("/v2/owner/plugin/manifests/v1.1", manifest_of("plugin-1.1.zip", &digest)),
+        ]);
+        let source = OciSource::new(url, None, Client::new(), Retry::default());
+        let history = source.history(&test_artifact(".jar")).expect("Failed to get history");
+        let builds: Vec<(&str, bool)> = history.iter().map(|b| (b.id.as_str(), b.has_artifacts)).collect();
+        assert_eq!(builds, [("v1.1", false), ("v1.0", true)]);
+    }
+
+    #[test]
+    fn test_download_errors() {
+        let digest = format!("sha256:{:x}", Sha256::digest(b"plugin jar"));
+        let url = serve(vec![
+            Route::new("/v2/owner/plugin/manifests/main", manifest_of("plugin-1.0.jar", &digest)),
+            Route::new(&format!("/v2/owner/plugin/blobs/{}", digest), "tampered jar"),
+        ]);
+        let source = OciSource::new(url, None, Client::new(), Retry::default());
+        assert!(matches!(
+            source.download(&test_artifact(".jar"), None),
+            Err(ErdError::ChecksumMismatch { .. })
+        ));
+        assert!(matches!(
+            source.download(&test_artifact(".jar"), Some("v0.9".into())),
+            Err(ErdError::NotFound { .. })
+        ));
+        // A registry that refuses anonymous access without offering a token
+        let url = serve(vec![Route::new("/v2/owner/plugin/manifests/main", "{}").with_status("401 Unauthorized")]);
+        let source = OciSource::new(url, None, Client::new(), Retry::default());
+        assert!(matches!(
+            source.download(&test_artifact(".jar"), None),
+            Err(ErdError::Unauthorized { .. })
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+//! Retrying requests that fail for reasons that may go away by themselves,
+//! such as dropped connections, timeouts, `5xx` responses and rate limits.
+//!
+//! Failed requests are retried with exponential backoff and jitter, unless the server says
+//! how long to wait with `Retry-After` or GitLab's `RateLimit-Reset`. Responses saying that
+//! the rate limit has been used up (`RateLimit-Remaining: 0`) hold back further requests to
+//! the same host until it resets, including those made by other threads.
+//!
+//! Only idempotent requests are retried, as a `POST` that timed out may still have been
+//! carried out by the server, e.g. queueing a rebuild.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+
+/// How many times a request is retried unless the source configures otherwise
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// The delay before the first retry, which doubles with each further retry
+const BASE_DELAY: Duration = Duration::from_secs(1);
+/// The longest that backoff alone waits between attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// The longest that erd is willing to wait when asked to by the server.
+/// Anything longer gives up, rather than seeming to hang
+const MAX_DELAY: Duration = Duration::from_secs(300);
+
+const RATELIMIT_REMAINING: &str = "ratelimit-remaining";
+const RATELIMIT_RESET: &str = "ratelimit-reset";
+
+/// Hosts whose rate limit has been used up, and when it resets
+static RATE_LIMITED: Mutex<Option<HashMap<String, SystemTime>>> = Mutex::new(None);
+
+/// How a source retries its requests
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    retries: u32,
+}
+
+impl Retry {
+    pub fn new(retries: u32) -> Self {
+        Self { retries }
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETRIES)
+    }
+}
+
+/// Sending a request with the given [`Retry`] policy
+pub trait SendRetrying {
+    /// Send the request, retrying it on transient failures if it is idempotent.
+    /// Gives the last response or error once out of retries
+    fn send_retrying(self, retry: &Retry) -> reqwest::Result<Response>;
+}
+
+impl SendRetrying for RequestBuilder {
+    fn send_retrying(self, retry: &Retry) -> reqwest::Result<Response> {
+        let built = self.try_clone().and_then(|r| r.build().ok());
+        let host = built.as_ref().and_then(|r| r.url().host_str().map(str::to_string));
+        let retries = match built.as_ref().map(|r| r.method().is_idempotent()) {
+            Some(true) => retry.retries,
+            _ => 0,
+        };
+        let mut attempt = 0;
+        loop {
+            if let Some(host) = &host {
+                wait_for_rate_limit(host);
+            }
+            // Requests with a streamed body can't be sent again
+            let request = match self.try_clone() {
+                Some(request) if attempt < retries => request,
+                _ => return self.send().inspect(note_rate_limit),
+            };
+            let result = request.send().inspect(note_rate_limit);
+            let (delay, reason) = match &result {
+                Ok(response) if is_transient_status(response.status()) => (
+                    retry_delay(response.headers(), attempt),
+                    response.status().to_string(),
+                ),
+                Err(e) if e.is_timeout() || e.is_connect() => (backoff(attempt), e.to_string()),
+                _ => return result,
+            };
+            if delay > MAX_DELAY {
+                return result;
+            }
+            warn!(
+                "Request failed ({}), retrying in {:.1}s ({}/{})",
+                reason,
+                delay.as_secs_f32(),
+                attempt + 1,
+                retries
+            );
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// How long to wait before retrying a failed response, as asked for by the server,
+/// or using backoff if it didn't say
+fn retry_delay(headers: &HeaderMap, attempt: u32) -> Duration {
+    let header = |name| headers.get(name)?.to_str().ok()?.trim().parse::<u64>().ok();
+    if let Some(seconds) = header(RETRY_AFTER.as_str()) {
+        return Duration::from_secs(seconds);
+    }
+    match header(RATELIMIT_RESET) {
+        Some(reset) => until(reset),
+        None => backoff(attempt),
+    }
+}
+
+/// Exponential backoff with full jitter, so that parallel requests don't retry in lockstep
+fn backoff(attempt: u32) -> Duration {
+    let max = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF);
+    max.mul_f64(0.5 + fastrand::f64() / 2.0)
+}
+
+/// The time left until the given unix timestamp
+fn until(timestamp: u64) -> Duration {
+    (UNIX_EPOCH + Duration::from_secs(timestamp))
+        .duration_since(SystemTime::now())
+        .unwrap_or_default()
+}
+
+/// Remember when the rate limit resets if the response used it up
+fn note_rate_limit(response: &Response) {
+    let header = |name| response.headers().get(name)?.to_str().ok()?.trim().parse::<u64>().ok();
+    if header(RATELIMIT_REMAINING) != Some(0) {
+        return;
+    }
+    let (Some(reset), Some(host)) = (header(RATELIMIT_RESET), response.url().host_str()) else {
+        return;
+    };
+    let mut limited = RATE_LIMITED.lock().unwrap();
+    limited
+        .get_or_insert_with(HashMap::new)
+        .insert(host.to_string(), UNIX_EPOCH + Duration::from_secs(reset));
+}
+
+/// Hold back a request until the host's rate limit resets, if it has been used up
+fn wait_for_rate_limit(host: &str) {
+    let reset = {
+        let mut limited = RATE_LIMITED.lock().unwrap();
+        let Some(limited) = limited.as_mut() else {
+            return;
+        };
+        match limited.get(host) {
+            Some(reset) if *reset > SystemTime::now() => *reset,
+            Some(_) => {
+                limited.remove(host);
+                return;
+            }
+            None => return,
+        }
+    };
+    let delay = reset.duration_since(SystemTime::now()).unwrap_or_default();
+    if delay > MAX_DELAY {
+        // Let the request fail, rather than seeming to hang
+        return;
+    }
+    info!("Rate limit reached for {}, waiting {}s", host, delay.as_secs());
+    thread::sleep(delay);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sources::test_server::{serve, Route};
+
+    #[test]
+    fn test_retries() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "7".parse().unwrap());
+        assert_eq!(retry_delay(&headers, 0), Duration::from_secs(7));
+        let backoff = retry_delay(&HeaderMap::new(), 2);
+        assert!(backoff >= Duration::from_secs(2) && backoff <= Duration::from_secs(4));
+
+        let url = serve(vec![
+            Route::new("/flaky", "{}")
+                .with_status("503 Service Unavailable")
+                .with_header("Retry-After: 0")
+                .times(1),
+            Route::new("/flaky", "ok"),
+        ]);
+        let client = reqwest::blocking::Client::new();
+        let response = client
+            .get(format!("{}/flaky", url))
+            .send_retrying(&Retry::new(1))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().unwrap(), "ok");
+
+        // Retrying a POST could carry it out twice
+        let url = serve(vec![
+            Route::new("/rebuild", "{}").with_status("503 Service Unavailable").times(1),
+            Route::new("/rebuild", "ok"),
+        ]);
+        let response = client
+            .post(format!("{}/rebuild", url))
+            .send_retrying(&Retry::new(1))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,206 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use crate::config::artifacts::ArtifactConfig;
+use crate::sources::ArtifactSource;
+
+/// A canned response served by [`serve`]
+pub struct Route {
+    pub path: String,
+    pub body: Vec<u8>,
+    /// Sent as the `ETag` of the body, which also makes the route honour `Range` requests
+    pub etag: Option<String>,
+    /// The status line to reply with instead of `200 OK`, e.g. `503 Service Unavailable`
+    pub status: Option<String>,
+    /// Further headers to send, e.g. `Retry-After: 0`
+    pub headers: Vec<String>,
+    /// How many requests the route answers before later routes with the same path take over
+    pub times: Option<usize>,
+    /// A header that requests must send, e.g. `Authorization: token secret`,
+    /// without which they get `401 Unauthorized`
+    pub required_header: Option<String>,
+}
+
+impl Route {
+    pub fn new(path: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            path: path.to_string(),
+            body: body.into(),
+            etag: None,
+            status: None,
+            headers: vec![],
+            times: None,
+            required_header: None,
+        }
+    }
+
+    pub fn with_etag(mut self, etag: &str) -> Self {
+        self.etag = Some(etag.to_string());
+        self
+    }
+
+    pub fn with_status(mut self, status: &str) -> Self {
+        self.status = Some(status.to_string());
+        self
+    }
+
+    pub fn with_header(mut self, header: &str) -> Self {
+        self.headers.push(header.to_string());
+        self
+    }
+
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    pub fn requiring_header(mut self, header: &str) -> Self {
+        self.required_header = Some(header.to_string());
+        self
+    }
+}
+
+/// Normalise a header line so that its name is compared case-insensitively
+fn normalise_header(header: &str) -> String {
+    match header.split_once(':') {
+        Some((name, value)) => format!("{}: {}", name.trim().to_lowercase(), value.trim()),
+        None => header.to_lowercase(),
+    }
+}
+
+/// Get the start of a `Range: bytes=<start>-` request, if the `If-Range` precondition (if any) holds
+fn get_range_start(route: &Route, range: Option<&str>, if_range: Option<&str>) -> Option<usize> {
+    let etag = route.etag.as_deref()?;
+    if if_range.is_some_and(|i| i != etag) {
+        return None;
+    }
+    let start = range?.strip_prefix("bytes=")?.strip_suffix('-')?.parse().ok()?;
+    (start < route.body.len()).then_some(start)
+}
+
+/// Serve the given routes over HTTP on a random local port, ignoring query strings.
+/// Returns the base URL of the server, which lives until the test process exits.
+pub fn serve(routes: Vec<Route>) -> String {
+    serve_with(|_| routes)
+}
+
+/// Like [`serve`], for routes whose bodies link back to the server, given its base URL
+pub fn serve_with(routes: impl FnOnce(&str) -> Vec<Route>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind stub server");
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let mut routes = routes(&url);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+            // Requests from erd that the routes answer never have a body
+            let (mut range, mut if_range) = (None, None);
+            let mut request_headers = vec![];
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok() && line.trim_end() != "" {
+                if let Some((name, value)) = line.trim_end().split_once(": ") {
+                    match name.to_lowercase().as_str() {
+                        "range" => range = Some(value.to_string()),
+                        "if-range" => if_range = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+                request_headers.push(normalise_header(&line));
+                line.clear();
+            }
+            let target = request_line.split(' ').nth(1).unwrap_or("");
+            let path = target.split('?').next().unwrap_or("");
+            let mut headers = String::new();
+            let route = routes.iter_mut().find(|r| r.path == path && r.times != Some(0));
+            let (status, body) = match route {
+                Some(route)
+                    if route
+                        .required_header
+                        .as_deref()
+                        .is_some_and(|h| !request_headers.contains(&normalise_header(h))) =>
+                {
+                    ("401 Unauthorized", &b"{}"[..])
+                }
+                Some(route) => {
+                    if let Some(times) = &mut route.times {
+                        *times -= 1;
+                    }
+                    for header in &route.headers {
+                        headers.push_str(&format!("{}\r\n", header));
+                    }
+                    if let Some(etag) = &route.etag {
+                        headers.push_str(&format!("ETag: {}\r\n", etag));
+                    }
+                    match get_range_start(route, range.as_deref(), if_range.as_deref()) {
+                        Some(start) => {
+                            let total = route.body.len();
+                            headers.push_str(&format!("Content-Range: bytes {}-{}/{}\r\n", start, total - 1, total));
+                            ("206 Partial Content", &route.body[start..])
+                        }
+                        None => (route.status.as_deref().unwrap_or("200 OK"), route.body.as_slice()),
+                    }
+                }
+                None => ("404 Not Found", &b"{}"[..]),
+            };
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+                status,
+                body.len(),
+                headers
+            );
+            let _ = stream.write_all(body);
+        }
+    });
+    url
+}
+
+/// Create an in-memory zip archive containing the given files
+pub fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    for (name, data) in files {
+        writer
+            .start_file(*name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(data).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+/// An artifact of the project `owner/plugin` on the `main` branch, for tests that need nothing more
+pub fn test_artifact(artifact_pattern: &str) -> ArtifactConfig {
+    ArtifactConfig {
+        id: "plugin".into(),
+        project_id: "owner/plugin".into(),
+        branch: "main".into(),
+        artifact_pattern: artifact_pattern.into(),
+        ..Default::default()
+    }
+}
+
+/// Download the artifact from the source, and check that its first file has the given name and data
+#[track_caller]
+pub fn assert_downloads(
+    source: &dyn ArtifactSource,
+    artifact: &ArtifactConfig,
+    build_id: Option<&str>,
+    file_name: &str,
+    data: &[u8],
+) {
+    let file_data = source
+        .download(artifact, build_id.map(str::to_string))
+        .expect("Failed to download")
+        .expect("Artifact was not found")
+        .files
+        .remove(0);
+    assert_eq!(file_data.file_name.to_str(), Some(file_name));
+    assert_eq!(file_data.data.to_vec(), data);
+}